@@ -0,0 +1,211 @@
+use std::io::Read;
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use serde_json::json;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::connection::{DeconzConnection, DeconzError};
+use crate::version::BACKUP_SW_VERSION;
+
+/// Name of the archive created by `POST /api/<key>/config/export`.
+pub const BACKUP_FILE_NAME: &str = "deCONZ.tar.gz";
+/// Where the Phoscon web app uploads a backup file before it imports it.
+///
+/// The documented REST API has no upload endpoint. The Phoscon app, which is served by the
+/// gateway itself, posts the file as the multipart part `file` to this path, relative to the
+/// gateway url. Use [`DeconzConnection::upload_backup_to`] if a gateway serves it elsewhere.
+pub const BACKUP_UPLOAD_PATH: &str = "upload.php";
+/// The Zigbee database, without it a backup can't be restored.
+const DATABASE_FILE_NAME: &str = "zll.db";
+
+/// The files found in a backup archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub files: Vec<String>,
+}
+
+/// Checks that `archive` is a gzipped tar archive that contains the gateway database.
+pub fn verify_backup(archive: &[u8]) -> Result<BackupInfo, DeconzError> {
+    let invalid = |reason: String| DeconzError::InvalidBackup(reason);
+    let mut tar = Vec::new();
+    GzDecoder::new(archive)
+        .read_to_end(&mut tar)
+        .map_err(|err| invalid(format!("not a gzip archive ({err})")))?;
+
+    let mut files = Vec::new();
+    let mut entries = tar::Archive::new(tar.as_slice());
+    for entry in entries.entries().map_err(|err| invalid(format!("not a tar archive ({err})")))? {
+        let entry = entry.map_err(|err| invalid(format!("corrupt tar entry ({err})")))?;
+        let path = entry.path().map_err(|err| invalid(format!("invalid file name ({err})")))?;
+        files.push(path.to_string_lossy().into_owned());
+    }
+
+    let has_database = files
+        .iter()
+        .any(|file| Path::new(file).file_name().is_some_and(|name| name == DATABASE_FILE_NAME));
+    if !has_database {
+        return Err(invalid(format!("{DATABASE_FILE_NAME} is missing")));
+    }
+    Ok(BackupInfo { files })
+}
+
+impl DeconzConnection {
+    /// Lets the gateway write a new backup archive, which can then be downloaded.
+    pub async fn create_backup(&self) -> Result<(), DeconzError> {
+        self.require_sw_version(BACKUP_SW_VERSION, "config/export").await?;
+        self.post::<_, serde_json::Value>("config/export", &json!({})).await?;
+        Ok(())
+    }
+
+    /// Downloads the archive written by [`Self::create_backup`] and returns its size in bytes.
+    pub async fn download_backup<W>(&self, writer: &mut W) -> Result<u64, DeconzError>
+        where
+            W: AsyncWrite + Unpin,
+    {
+        let url = self.url.join(BACKUP_FILE_NAME)?;
+        let mut response = self.client().get(url).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(DeconzError::Status { status, body });
+        }
+
+        let mut size = 0;
+        while let Some(chunk) = response.chunk().await? {
+            writer.write_all(&chunk).await.map_err(io_error)?;
+            size += chunk.len() as u64;
+        }
+        writer.flush().await.map_err(io_error)?;
+        Ok(size)
+    }
+
+    pub async fn download_backup_to_file(&self, path: impl AsRef<Path>) -> Result<u64, DeconzError> {
+        let mut file = tokio::fs::File::create(path).await.map_err(io_error)?;
+        self.download_backup(&mut file).await
+    }
+
+    /// Uploads a backup archive to [`BACKUP_UPLOAD_PATH`], so it can be restored with
+    /// [`Self::import_backup`].
+    pub async fn upload_backup(&self, archive: Vec<u8>) -> Result<(), DeconzError> {
+        self.upload_backup_to(BACKUP_UPLOAD_PATH, archive).await
+    }
+
+    /// Uploads a backup archive to `path`, relative to the gateway url.
+    pub async fn upload_backup_to(&self, path: &str, archive: Vec<u8>) -> Result<(), DeconzError> {
+        let url = self.url.join(path)?;
+        let part = reqwest::multipart::Part::bytes(archive)
+            .file_name(BACKUP_FILE_NAME)
+            .mime_str("application/gzip")?;
+        let form = reqwest::multipart::Form::new().part("file", part);
+        let response = self.client().post(url).multipart(form).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(DeconzError::Status { status, body });
+        }
+        Ok(())
+    }
+
+    /// Restores the uploaded backup. The gateway restarts afterwards.
+    pub async fn import_backup(&self) -> Result<(), DeconzError> {
+        self.require_sw_version(BACKUP_SW_VERSION, "config/import").await?;
+        self.post::<_, serde_json::Value>("config/import", &json!({})).await?;
+        Ok(())
+    }
+
+    /// Verifies, uploads and imports a backup archive.
+    pub async fn restore_backup(&self, archive: Vec<u8>) -> Result<BackupInfo, DeconzError> {
+        let info = verify_backup(&archive)?;
+        self.require_sw_version(BACKUP_SW_VERSION, "config/import").await?;
+        self.upload_backup(archive).await?;
+        self.import_backup().await?;
+        Ok(info)
+    }
+}
+
+fn io_error(err: std::io::Error) -> DeconzError {
+    DeconzError::Io(std::sync::Arc::new(err))
+}
+
+
+#[cfg(test)]
+mod backup_tests {
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use httpmock::prelude::*;
+    use url::Url;
+
+    use crate::backup::verify_backup;
+    use crate::connection::{DeconzConnection, DeconzError};
+
+    fn archive(files: &[&str]) -> Vec<u8> {
+        let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for file in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(4);
+            header.set_cksum();
+            tar.append_data(&mut header, file, "data".as_bytes()).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn test_verify_backup() {
+        let info = verify_backup(&archive(&["deCONZ.conf", "zll.db"])).unwrap();
+        assert_eq!(info.files, vec!["deCONZ.conf".to_string(), "zll.db".to_string()]);
+
+        assert!(matches!(verify_backup(&archive(&["deCONZ.conf"])), Err(DeconzError::InvalidBackup(_))));
+        assert!(matches!(verify_backup(b"not an archive"), Err(DeconzError::InvalidBackup(_))));
+    }
+
+    #[tokio::test]
+    async fn test_restore_backup() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/config");
+            then.status(200).body(include_str!("test-api-responses/get-configuration.json"));
+        });
+        let upload = server.mock(|when, then| {
+            when.method(POST)
+                .path("/upload.php")
+                .header_exists("content-type")
+                .body_contains(r#"name="file"; filename="deCONZ.tar.gz""#)
+                .body_contains("application/gzip");
+            then.status(200);
+        });
+        let import = server.mock(|when, then| {
+            when.method(POST).path("/api/KEY/config/import");
+            then.status(200).body(r#"[{"success": {"/config/import": "success"}}]"#);
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let connection = DeconzConnection::new(url, "KEY".to_string()).unwrap();
+
+        let info = connection.restore_backup(archive(&["zll.db"])).await.unwrap();
+        assert_eq!(info.files, vec!["zll.db".to_string()]);
+        upload.assert();
+        import.assert();
+
+        let result = connection.restore_backup(archive(&["deCONZ.conf"])).await;
+        assert!(matches!(result, Err(DeconzError::InvalidBackup(_))));
+        upload.assert_hits(1);
+        import.assert_hits(1);
+    }
+
+    #[tokio::test]
+    async fn test_download_backup() {
+        let server = MockServer::start();
+        let backup = archive(&["zll.db"]);
+        server.mock(|when, then| {
+            when.method(GET).path("/deCONZ.tar.gz");
+            then.status(200).body(&backup);
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let connection = DeconzConnection::new(url, "KEY".to_string()).unwrap();
+
+        let mut downloaded = Vec::new();
+        let size = connection.download_backup(&mut downloaded).await.unwrap();
+        assert_eq!(size as usize, backup.len());
+        assert_eq!(downloaded, backup);
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+use tokio::sync::oneshot;
+
+use crate::connection::DeconzError;
+use crate::endpoints::light::LightState;
+use crate::rate_limit::Resource;
+
+#[derive(Debug)]
+struct Batch<T> {
+    state: LightState,
+    waiters: Vec<oneshot::Sender<Result<T, DeconzError>>>,
+}
+
+/// Merges light states that are submitted for the same resource while an earlier one is still
+/// waiting to be sent.
+///
+/// The first caller for a resource becomes the leader of a batch: it waits until it may send,
+/// then sends the merged state of everyone who joined in the meantime and shares the result.
+#[derive(Debug)]
+pub(crate) struct Coalescer<T> {
+    pending: Mutex<HashMap<Resource, Batch<T>>>,
+}
+
+/// Discards the batch if the leader is cancelled, so followers don't wait forever.
+///
+/// Once the leader took its batch, the resource may already have a new batch with a new leader,
+/// so the guard must not remove anything anymore.
+struct Leader<'a, T> {
+    coalescer: &'a Coalescer<T>,
+    resource: &'a Resource,
+    taken: bool,
+}
+
+impl<T> Leader<'_, T> {
+    fn take_batch(mut self) -> Option<Batch<T>> {
+        self.taken = true;
+        self.coalescer.pending.lock().unwrap().remove(self.resource)
+    }
+}
+
+impl<T> Drop for Leader<'_, T> {
+    fn drop(&mut self) {
+        if !self.taken {
+            self.coalescer.pending.lock().unwrap().remove(self.resource);
+        }
+    }
+}
+
+impl<T: Clone> Coalescer<T> {
+    pub(crate) fn new() -> Coalescer<T> {
+        Coalescer {
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Submits `state` for `resource`.
+    ///
+    /// `ready` resolves once the command may be sent, `send` sends the merged state.
+    pub(crate) async fn submit<Ready, Send, Sent>(
+        &self,
+        resource: &Resource,
+        state: &LightState,
+        ready: Ready,
+        send: Send,
+    ) -> Result<T, DeconzError>
+        where
+            Ready: Future<Output=Result<(), DeconzError>>,
+            Send: FnOnce(LightState) -> Sent,
+            Sent: Future<Output=Result<T, DeconzError>>,
+    {
+        let follower = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.get_mut(resource) {
+                Some(batch) => {
+                    batch.state.merge(state);
+                    let (sender, receiver) = oneshot::channel();
+                    batch.waiters.push(sender);
+                    Some(receiver)
+                }
+                None => {
+                    pending.insert(resource.clone(), Batch {
+                        state: *state,
+                        waiters: vec![],
+                    });
+                    None
+                }
+            }
+        };
+        if let Some(receiver) = follower {
+            return receiver
+                .await
+                .unwrap_or_else(|_| Err(DeconzError::Dropped(resource.to_string())));
+        }
+
+        let leader = Leader {
+            coalescer: self,
+            resource,
+            taken: false,
+        };
+        let ready = ready.await;
+        let batch = leader.take_batch().expect("batch is only removed by its leader");
+
+        let result = match ready {
+            Ok(()) => send(batch.state).await,
+            Err(err) => Err(err),
+        };
+        for waiter in batch.waiters {
+            let _ = waiter.send(result.clone());
+        }
+        result
+    }
+}
+
+
+#[cfg(test)]
+mod coalesce_tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::coalesce::Coalescer;
+    use crate::endpoints::light::LightState;
+    use crate::rate_limit::Resource;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_concurrent_submits() {
+        let coalescer = Arc::new(Coalescer::<u32>::new());
+        let sent = Arc::new(AtomicU32::new(0));
+        let tasks: Vec<_> = (0..64u32)
+            .map(|i| {
+                let coalescer = coalescer.clone();
+                let sent = sent.clone();
+                tokio::spawn(async move {
+                    let resource = Resource::Light("1".to_string());
+                    for round in 0..200u32 {
+                        let state = LightState { bri: Some((i + round) as u8), ..Default::default() };
+                        let ready = async { Ok(()) };
+                        let send = |_| async { Ok(sent.fetch_add(1, Ordering::SeqCst)) };
+                        coalescer.submit(&resource, &state, ready, send).await?;
+                    }
+                    Ok::<_, crate::connection::DeconzError>(())
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.expect("submit panicked").expect("command was dropped");
+        }
+        assert!(sent.load(Ordering::SeqCst) >= 1);
+        assert!(coalescer.pending.lock().unwrap().is_empty());
+    }
+}
@@ -1,16 +1,32 @@
 use std::collections::HashMap;
-use reqwest::StatusCode;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use reqwest::{Method, StatusCode};
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
 use serde_repr::*;
 use url::Url;
 
-use crate::endpoints::light::{Light, LightState};
+use crate::coalesce::Coalescer;
+use crate::endpoints::light::{Light, LightAttributes, LightConfig, LightState, LightStateError};
 use crate::endpoints::configuration::ApiToken;
+use crate::endpoints::configuration::ConfigUpdate;
+use crate::endpoints::configuration::{FactoryReset, FactoryResetResult, ResetConfirmation};
+use crate::endpoints::configuration::{GatewayConfig, GatewayInfo};
+use crate::endpoints::configuration::PasswordChange;
+use crate::endpoints::configuration::WhitelistEntry;
 use crate::endpoints::configuration::TokenRequest;
+use crate::endpoints::configuration::UpdateState;
+use crate::endpoints::full_state::FullState;
+use crate::endpoints::groups::CreateGroupRequest;
+use crate::rate_limit::{RateLimit, RateLimiter, Resource};
+use crate::retry::{RetryCounters, RetryPolicy, RetryStats};
+use crate::version::{ApiVersion, GatewayVersion, WEBSOCKET_NOTIFY_ALL_SW_VERSION};
 
 
-#[derive(Debug, Serialize_repr, Deserialize_repr)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
 #[repr(u16)]
 pub enum DeconzErrorType {
     UnauthorizedUser = 1,
@@ -39,7 +55,7 @@ pub enum DeconzErrorType {
     DeviceScenesTableFull = 402,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum RequestResponse<SuccessType> {
     #[serde(rename(serialize = "error", deserialize = "error"))]
     Error {
@@ -51,12 +67,354 @@ pub enum RequestResponse<SuccessType> {
     Success(SuccessType),
 }
 
-struct Error {
-    r#type: DeconzErrorType,
-    address: String,
-    description: String,
+/// Success payload of requests that create or delete a resource, e.g. `{"id": "3"}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ResourceId {
+    pub id: String,
 }
 
+/// Confirmation of a deleted resource.
+///
+/// Most endpoints answer with the deleted id, some only with a message like `"/lights/1 deleted"`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum DeleteConfirmation {
+    Id(ResourceId),
+    Message(String),
+}
+
+/// An error reported by the gateway itself, as found in `[{"error": {...}}]` responses.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Error {
+    pub r#type: DeconzErrorType,
+    pub address: String,
+    pub description: String,
+}
+
+impl<T> From<Error> for RequestResponse<T> {
+    fn from(err: Error) -> Self {
+        RequestResponse::Error {
+            r#type: err.r#type,
+            address: err.address,
+            description: err.description,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Api endpoint {} failed ('{}', type: {:?})", self.address, self.description, self.r#type)
+    }
+}
+
+/// Errors returned by [`DeconzConnection`].
+///
+/// Errors are cheap to clone, so the result of a coalesced command can be handed to every caller.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum DeconzError {
+    #[error("Network error {0}")]
+    Network(#[source] Arc<reqwest::Error>),
+    #[error("Unexpected HTTP status {status}")]
+    Status {
+        status: StatusCode,
+        body: String,
+    },
+    #[error("Deserializing failed with: {source}")]
+    Decode {
+        #[source]
+        source: Arc<serde_json::Error>,
+        body: String,
+    },
+    #[error("{0}")]
+    Gateway(Error),
+    /// Returned when a response mixes `success` and `error` entries, or carries more than one error.
+    #[error("Request partially failed ({} succeeded, {} failed)", succeeded.len(), failed.len())]
+    PartialSuccess {
+        succeeded: Vec<serde_json::Value>,
+        failed: Vec<Error>,
+    },
+    #[error("Failed to build url: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("Invalid gateway url '{url}': {reason}")]
+    InvalidUrl {
+        url: String,
+        reason: &'static str,
+    },
+    #[error("Giving up after {attempts} attempts: {source}")]
+    RetriesExhausted {
+        attempts: u32,
+        #[source]
+        source: Box<DeconzError>,
+    },
+    /// A retried request failed with an error that is not retried, e.g. a 404 on the second attempt.
+    #[error("Failed on attempt {attempts}: {source}")]
+    FailedAfterRetry {
+        attempts: u32,
+        #[source]
+        source: Box<DeconzError>,
+    },
+    #[error("Command for {0} was dropped in favour of newer commands")]
+    Dropped(String),
+    /// A value was rejected before it was sent to the gateway.
+    #[error("Invalid value for parameter {parameter}: {reason}")]
+    InvalidParameter {
+        parameter: &'static str,
+        reason: String,
+    },
+    #[error("The link button was not pressed within {attempts} attempts")]
+    PairingTimeout {
+        attempts: u32,
+    },
+    #[error("Software update did not finish in time (last state: {last_state:?})")]
+    UpdateTimeout {
+        last_state: UpdateState,
+    },
+    #[error("Factory reset was confirmed for bridge {confirmed}, but the gateway is {actual}")]
+    ResetNotConfirmed {
+        confirmed: String,
+        actual: String,
+    },
+    #[error("Invalid backup archive: {0}")]
+    InvalidBackup(String),
+    #[error("I/O error {0}")]
+    Io(#[source] Arc<std::io::Error>),
+    #[error("{feature} requires gateway version {required}, but the gateway runs {actual}")]
+    UnsupportedVersion {
+        feature: &'static str,
+        required: ApiVersion,
+        actual: ApiVersion,
+    },
+    #[error("Invalid light state: {0}")]
+    InvalidLightState(#[from] LightStateError),
+    #[error("Invalid api key: {0}")]
+    InvalidApiKey(&'static str),
+    #[error("Failed to create client: {0}")]
+    Client(#[source] Arc<reqwest::Error>),
+}
+
+impl From<reqwest::Error> for DeconzError {
+    fn from(err: reqwest::Error) -> Self {
+        DeconzError::Network(Arc::new(err))
+    }
+}
+
+impl DeconzError {
+    /// The gateway error type, if the gateway rejected (part of) the request.
+    pub fn error_type(&self) -> Option<DeconzErrorType> {
+        match self {
+            DeconzError::Gateway(err) => Some(err.r#type),
+            DeconzError::PartialSuccess { failed, .. } => failed.first().map(|err| err.r#type),
+            DeconzError::RetriesExhausted { source, .. } | DeconzError::FailedAfterRetry { source, .. } => source.error_type(),
+            _ => None,
+        }
+    }
+
+    /// The HTTP status of the response, if one was received and it was not successful.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            DeconzError::Status { status, .. } => Some(*status),
+            DeconzError::Network(err) => err.status(),
+            DeconzError::RetriesExhausted { source, .. } | DeconzError::FailedAfterRetry { source, .. } => source.status(),
+            _ => None,
+        }
+    }
+
+    /// How many attempts were made before the request failed, `None` if it wasn't retried.
+    pub fn attempts(&self) -> Option<u32> {
+        match self {
+            DeconzError::RetriesExhausted { attempts, .. } | DeconzError::FailedAfterRetry { attempts, .. } => Some(*attempts),
+            _ => None,
+        }
+    }
+
+    /// The error of the last attempt, without the retry information.
+    pub fn last_error(&self) -> &DeconzError {
+        match self {
+            DeconzError::RetriesExhausted { source, .. } | DeconzError::FailedAfterRetry { source, .. } => source.last_error(),
+            err => err,
+        }
+    }
+
+    pub(crate) fn decode(source: serde_json::Error, body: &str) -> DeconzError {
+        DeconzError::Decode {
+            source: Arc::new(source),
+            body: body.to_string(),
+        }
+    }
+}
+
+/// Checks a response for gateway errors and HTTP failures before it is deserialized.
+///
+/// deCONZ reports failures as `[{"error": {...}}]` arrays, usually together with a 4xx status, so
+/// error entries take precedence over the bare status code.
+fn check_response(status: StatusCode, body: &str) -> Result<(), DeconzError> {
+    let entries = match serde_json::from_str::<Vec<RequestResponse<serde_json::Value>>>(body) {
+        Ok(entries) => entries,
+        Err(_) => match serde_json::from_str::<RequestResponse<serde_json::Value>>(body) {
+            Ok(entry) => vec![entry],
+            Err(_) => vec![],
+        },
+    };
+
+    let mut succeeded = vec![];
+    let mut failed = vec![];
+    for entry in entries {
+        match entry {
+            RequestResponse::Success(value) => succeeded.push(value),
+            RequestResponse::Error { r#type, address, description } => {
+                failed.push(Error { r#type, address, description })
+            }
+        }
+    }
+    if succeeded.is_empty() && failed.len() == 1 {
+        return Err(DeconzError::Gateway(failed.remove(0)));
+    }
+    if !failed.is_empty() {
+        return Err(DeconzError::PartialSuccess { succeeded, failed });
+    }
+
+    if status.is_client_error() || status.is_server_error() {
+        return Err(DeconzError::Status {
+            status,
+            body: body.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Takes the first `success` entry of a response that passed [`check_response`].
+///
+/// Takes the response as JSON, so decode errors can carry it as their body.
+fn first_success<T: DeserializeOwned>(response: serde_json::Value) -> Result<T, DeconzError> {
+    let responses = Vec::<RequestResponse<T>>::deserialize(&response).map_err(|err| DeconzError::decode(err, &response.to_string()))?;
+    responses
+        .into_iter()
+        .find_map(|response| match response {
+            RequestResponse::Success(success) => Some(success),
+            RequestResponse::Error { .. } => None,
+        })
+        .ok_or_else(|| DeconzError::decode(serde::de::Error::custom("no success entry in response"), &response.to_string()))
+}
+
+/// Per-field outcome of a request, keyed by the address below the changed resource, e.g.
+/// `zigbeechannel` or `bri/startup`.
+pub type FieldResults = HashMap<String, Result<serde_json::Value, Error>>;
+
+/// Splits the response to a request on `resource`, e.g. `/lights/1/state`, into per-field results
+/// keyed by the address below it, e.g. `bri`.
+///
+/// Only gateway errors for fields of `resource` become entries. Other errors, like an unauthorized
+/// user, a missing resource or a busy gateway, fail the whole request.
+fn field_results(
+    response: Result<Vec<RequestResponse<serde_json::Value>>, DeconzError>,
+    resource: &str,
+) -> Result<FieldResults, DeconzError> {
+    // Errors for the whole resource name the field only in the description.
+    let error_field = |err: &Error| match field_below(resource, &err.address) {
+        None if err.address == resource => rejected_parameter(err),
+        field => field,
+    };
+    let entries = match response {
+        Ok(entries) => entries,
+        Err(DeconzError::Gateway(err)) if error_field(&err).is_some() => vec![err.into()],
+        Err(DeconzError::PartialSuccess { succeeded, failed }) if failed.iter().all(|err| error_field(err).is_some()) => succeeded
+            .into_iter()
+            .map(RequestResponse::Success)
+            .chain(failed.into_iter().map(RequestResponse::from))
+            .collect(),
+        Err(err) => return Err(err),
+    };
+
+    let mut results = FieldResults::new();
+    for entry in entries {
+        match entry {
+            RequestResponse::Success(serde_json::Value::Object(values)) => {
+                for (address, value) in values {
+                    results.insert(field_below(resource, &address).unwrap_or(address), Ok(value));
+                }
+            }
+            RequestResponse::Success(_) => {}
+            RequestResponse::Error { r#type, address, description } => {
+                let err = Error { r#type, address, description };
+                results.insert(error_field(&err).unwrap_or_else(|| err.address.clone()), Err(err));
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// The address of a field below `resource`, e.g. `bri/startup` for `/lights/1/config/bri/startup`.
+fn field_below(resource: &str, address: &str) -> Option<String> {
+    let field = address.strip_prefix(resource)?.strip_prefix('/')?;
+    (!field.is_empty()).then(|| field.to_string())
+}
+
+/// What the gateway applied of a state sent with [`DeconzConnection::set_light_state`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LightStateResult {
+    /// The accepted fields with the values the gateway confirmed, other fields are `None`.
+    pub accepted: LightState,
+    /// The rejected fields keyed by their api name, e.g. `bri`.
+    pub rejected: HashMap<String, Error>,
+}
+
+impl LightStateResult {
+    pub fn is_fully_applied(&self) -> bool {
+        self.rejected.is_empty()
+    }
+
+    fn from_response(response: Result<StateResponse, DeconzError>, resource: &str) -> Result<LightStateResult, DeconzError> {
+        let response = response.map(|entries| {
+            entries
+                .into_iter()
+                .map(|entry| match entry {
+                    RequestResponse::Success(values) => RequestResponse::Success(serde_json::Value::Object(values.into_iter().collect())),
+                    RequestResponse::Error { r#type, address, description } => RequestResponse::Error { r#type, address, description },
+                })
+                .collect()
+        });
+
+        let mut accepted = serde_json::Map::new();
+        let mut rejected = HashMap::new();
+        for (field, result) in field_results(response, resource)? {
+            match result {
+                Ok(value) => {
+                    accepted.insert(field, value);
+                }
+                Err(err) => {
+                    rejected.insert(field, err);
+                }
+            }
+        }
+        let accepted = serde_json::Value::Object(accepted);
+        Ok(LightStateResult {
+            accepted: serde_json::from_value(accepted.clone()).map_err(|err| DeconzError::decode(err, &accepted.to_string()))?,
+            rejected,
+        })
+    }
+}
+
+/// Errors for a whole state, like `/lights/1/state`, name the field only in the description,
+/// e.g. `invalid value, 1000, for parameter, bri`.
+fn rejected_parameter(err: &Error) -> Option<String> {
+    let mut parts = err.description.split(", ").skip_while(|part| !part.ends_with("parameter"));
+    parts.next()?;
+    parts.next().map(str::to_string)
+}
+
+async fn execute<Response>(request: reqwest::RequestBuilder) -> Result<Response, DeconzError>
+    where
+        Response: DeserializeOwned,
+{
+    let response = request.send().await?;
+    let status = response.status();
+    let body = response.text().await?;
+    check_response(status, &body)?;
+    serde_json::from_str::<Response>(&body).map_err(|err| DeconzError::decode(err, &body))
+}
+
+/// Per-field results of setting a light or group state.
+type StateResponse = Vec<RequestResponse<HashMap<String, serde_json::Value>>>;
 
 #[derive(Debug)]
 pub struct DeconzConnection {
@@ -64,147 +422,1583 @@ pub struct DeconzConnection {
     pub api_key: String,
     api_url: Url,
     client: reqwest::Client,
+    retry_policy: Option<RetryPolicy>,
+    retry_counters: RetryCounters,
+    rate_limiter: Option<RateLimiter>,
+    coalescer: Option<Coalescer<StateResponse>>,
+    /// Updated by every [`Self::get_config`], so it follows software updates.
+    version: Mutex<Option<GatewayVersion>>,
+    validate_light_state: bool,
+    /// Lights seen by [`Self::get_all_lights`] and [`Self::get_light`], used for validation.
+    known_lights: Mutex<HashMap<String, Light>>,
 }
 
 
-impl DeconzConnection {
-    pub fn new(url: Url, api_key: String) -> Result<DeconzConnection, Box<dyn std::error::Error>> {
-        let api_url = url.join("api/")?.join(&format!("{api_key}/"))?;
-        let client = reqwest::ClientBuilder::new()
-            .build()
-            .expect("Failed to create client.");
+/// Builds a [`DeconzConnection`] with custom client settings.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use deconz_rs::connection::DeconzConnection;
+/// # fn main() -> Result<(), deconz_rs::connection::DeconzError> {
+/// let connection = DeconzConnection::builder("http://192.168.0.166".parse().unwrap())
+///     .api_key("D453E7BAF8")
+///     .timeout(Duration::from_secs(5))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct DeconzConnectionBuilder {
+    url: Url,
+    api_key: Option<String>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    proxy: Option<reqwest::Proxy>,
+    user_agent: Option<String>,
+    client: Option<reqwest::Client>,
+    retry_policy: Option<RetryPolicy>,
+    rate_limit: Option<RateLimit>,
+    coalesce_commands: bool,
+    validate_light_state: bool,
+}
 
-        Ok(DeconzConnection {
+impl DeconzConnectionBuilder {
+    pub fn new(url: Url) -> DeconzConnectionBuilder {
+        DeconzConnectionBuilder {
             url,
-            api_key,
-            api_url,
-            client,
-        })
+            api_key: None,
+            timeout: None,
+            connect_timeout: None,
+            proxy: None,
+            user_agent: None,
+            client: None,
+            retry_policy: None,
+            rate_limit: None,
+            coalesce_commands: false,
+            validate_light_state: true,
+        }
+    }
+
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Total timeout for each request, from sending it until the body has been read.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Uses an existing client. Timeouts, proxy and user agent set on this builder are ignored.
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Retries requests that failed because the gateway was busy or unreachable. Disabled by default.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Queues light and group commands so they don't flood the Zigbee network. Disabled by default.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Merges light states sent to the same light or group while an earlier one is still queued,
+    /// so only the freshest state is sent. Most useful together with [`Self::rate_limit`].
+    pub fn coalesce_commands(mut self, coalesce_commands: bool) -> Self {
+        self.coalesce_commands = coalesce_commands;
+        self
+    }
+
+    /// Checks light states with [`LightState::validate`] before sending them. Enabled by default.
+    pub fn validate_light_state(mut self, validate_light_state: bool) -> Self {
+        self.validate_light_state = validate_light_state;
+        self
+    }
+
+    /// Builds the connection using the api key set with [`DeconzConnectionBuilder::api_key`].
+    pub fn build(mut self) -> Result<DeconzConnection, DeconzError> {
+        let api_key = self.api_key.take().ok_or(DeconzError::InvalidApiKey("no api key given"))?;
+        let url = self.base_url()?;
+        let client = self.build_client()?;
+        self.finish(url, api_key, client)
+    }
+
+    /// Requests a new api key from the gateway and builds the connection with it.
+    ///
+    /// The gateway has to be unlocked ("Authenticate app" in Phoscon) for this to succeed.
+    pub async fn build_with_token(mut self, requested_user: TokenRequest) -> Result<DeconzConnection, DeconzError> {
+        let url = self.base_url()?;
+        let client = self.build_client()?;
+        let api_key = request_token(&client, &url, &requested_user).await?;
+        self.finish(url, api_key, client)
+    }
+
+    /// Fetches the basic gateway configuration with the client settings of this builder, see [`probe`].
+    ///
+    /// Uses [`PROBE_TIMEOUT`] unless a timeout or a client was set, so probing a host that
+    /// accepts connections but never answers doesn't hang.
+    pub async fn probe(mut self) -> Result<GatewayInfo, DeconzError> {
+        let url = self.base_url()?;
+        self.timeout.get_or_insert(PROBE_TIMEOUT);
+        let client = self.build_client()?;
+        execute(client.get(url.join("api/config")?)).await
+    }
+
+    pub(crate) fn base_url(&self) -> Result<Url, DeconzError> {
+        validate_base_url(self.url.clone())
+    }
+
+    pub(crate) fn build_client(&mut self) -> Result<reqwest::Client, DeconzError> {
+        if let Some(client) = self.client.take() {
+            return Ok(client);
+        }
+        let mut builder = reqwest::ClientBuilder::new();
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(proxy) = self.proxy.take() {
+            builder = builder.proxy(proxy);
+        }
+        if let Some(user_agent) = self.user_agent.take() {
+            builder = builder.user_agent(user_agent);
+        }
+        builder.build().map_err(|err| DeconzError::Client(Arc::new(err)))
+    }
+
+    pub(crate) fn finish(self, url: Url, api_key: String, client: reqwest::Client) -> Result<DeconzConnection, DeconzError> {
+        let mut connection = DeconzConnection::from_parts(url, api_key, client)?;
+        connection.retry_policy = self.retry_policy;
+        connection.rate_limiter = self.rate_limit.map(RateLimiter::new);
+        if self.coalesce_commands {
+            connection.coalescer = Some(Coalescer::new());
+        }
+        connection.validate_light_state = self.validate_light_state;
+        Ok(connection)
+    }
+}
+
+/// How long [`probe`] waits for an answer if no other timeout was set.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests a new api key with `POST /api`.
+pub(crate) async fn request_token(client: &reqwest::Client, url: &Url, requested_user: &TokenRequest) -> Result<String, DeconzError> {
+    let request = client.post(url.join("api")?).json(requested_user);
+    let token_response: ApiToken = first_success(execute(request).await?)?;
+    Ok(token_response.username)
+}
+
+/// Fetches the basic gateway configuration, which deCONZ returns without an api key.
+///
+/// Use it to check that `url` points to a deCONZ gateway before pairing with it. Gives up after
+/// [`PROBE_TIMEOUT`], use [`DeconzConnectionBuilder::probe`] for other client settings.
+pub async fn probe(url: Url) -> Result<GatewayInfo, DeconzError> {
+    DeconzConnectionBuilder::new(url).probe().await
+}
+
+/// Api keys end up in url paths, so they must not contain path or query delimiters.
+fn validate_api_key(api_key: &str) -> Result<(), DeconzError> {
+    if api_key.is_empty() || api_key.contains(['/', '?', '#']) {
+        return Err(DeconzError::InvalidApiKey("must be non-empty and must not contain '/', '?' or '#'"));
+    }
+    Ok(())
+}
+
+/// Makes sure the gateway url can be used as a base for relative joins.
+fn validate_base_url(mut url: Url) -> Result<Url, DeconzError> {
+    let invalid = |url: &Url, reason| DeconzError::InvalidUrl {
+        url: url.to_string(),
+        reason,
+    };
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(invalid(&url, "scheme must be http or https"));
+    }
+    if url.host().is_none() {
+        return Err(invalid(&url, "missing host"));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(invalid(&url, "must not contain a query or fragment"));
+    }
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    Ok(url)
+}
+
+
+impl DeconzConnection {
+    pub fn builder(url: Url) -> DeconzConnectionBuilder {
+        DeconzConnectionBuilder::new(url)
+    }
+
+    pub fn new(url: Url, api_key: String) -> Result<DeconzConnection, DeconzError> {
+        DeconzConnectionBuilder::new(url).api_key(api_key).build()
     }
 
     pub async fn new_without_key(
         url: Url,
         requested_user: TokenRequest,
-    ) -> Result<DeconzConnection, Box<dyn std::error::Error>> {
-        let api_path = url.join("api").expect("failed to build url");
-        let client = reqwest::ClientBuilder::new()
-            .build()
-            .expect("Failed to create client.");
-
-        let response = match client
-            .post(api_path.clone())
-            .json(&requested_user)
-            .send()
-            .await
-        {
-            Ok(response) => response,
-            Err(err) => return Err(format!("Network error {}", err).into()),
-        };
-        let token_response = match response.json::<Vec<RequestResponse<ApiToken>>>().await {
-            Ok(mut res) => {
-                let res = res.remove(0);
-                match res {
-                    RequestResponse::Success(token_response) => { token_response }
-                    RequestResponse::Error { address, description, r#type } => {
-                        return Err(format!("Api endpoint {address} failed ('{description}', type: {:?})", r#type).into());
-                    }
-                }
-            }
-            Err(err) => return Err(format!("Unexpected API response with error {err}").into()),
-        };
+    ) -> Result<DeconzConnection, DeconzError> {
+        DeconzConnectionBuilder::new(url).build_with_token(requested_user).await
+    }
 
-        let api_key = &token_response.username;
-        let api_url = url.join("api/").unwrap().join(api_key).unwrap();
+    fn from_parts(url: Url, api_key: String, client: reqwest::Client) -> Result<DeconzConnection, DeconzError> {
+        validate_api_key(&api_key)?;
+        let api_url = url.join("api/")?.join(&format!("{api_key}/"))?;
 
         Ok(DeconzConnection {
-            client,
             url,
-            api_key: api_key.to_string(),
+            api_key,
             api_url,
+            client,
+            retry_policy: None,
+            retry_counters: RetryCounters::default(),
+            rate_limiter: None,
+            coalescer: None,
+            version: Mutex::new(None),
+            validate_light_state: true,
+            known_lights: Mutex::new(HashMap::new()),
         })
     }
 
-
     pub fn get_api_key(&self) -> &String {
         &self.api_key
     }
 
+    pub(crate) fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    pub(crate) fn version_cache(&self) -> &Mutex<Option<GatewayVersion>> {
+        &self.version
+    }
 
-    async fn get_request<Response>(&self, url: Url) -> Result<Response, Box<dyn std::error::Error>>
+
+    async fn request<T, Response>(&self, method: Method, url: Url, data: Option<&T>) -> Result<Response, DeconzError>
         where
+            T: Serialize + ?Sized,
             Response: DeserializeOwned,
     {
-        let response = match self.client.get(url).send().await {
-            Ok(response) => response,
-            Err(err) => return Err(format!("Network error {}", err).into()),
-        };
-        if response.status() == StatusCode::FORBIDDEN {
-            return Err("unauthorized user".to_string().into());
+        Ok(self.request_with_attempts(method, url, data, None).await?.0)
+    }
+
+    /// Sends a request, retrying it according to the connection's [`RetryPolicy`], and returns
+    /// the response with the number of attempts it took.
+    ///
+    /// Retries of commands to `resource` wait for the rate limiter like any other command.
+    async fn request_with_attempts<T, Response>(
+        &self,
+        method: Method,
+        url: Url,
+        data: Option<&T>,
+        resource: Option<&Resource>,
+    ) -> Result<(Response, u32), DeconzError>
+        where
+            T: Serialize + ?Sized,
+            Response: DeserializeOwned,
+    {
+        let mut attempt = 1;
+        loop {
+            let mut request = self.client.request(method.clone(), url.clone());
+            if let Some(data) = data {
+                request = request.json(data);
+            }
+            let err = match execute(request).await {
+                Ok(response) => {
+                    self.retry_counters.record(attempt);
+                    return Ok((response, attempt));
+                }
+                Err(err) => err,
+            };
+
+            let retry = self.retry_policy.as_ref().filter(|policy| policy.should_retry(&method, &err));
+            let err = match retry {
+                Some(policy) if attempt < policy.max_attempts => {
+                    tokio::time::sleep(policy.backoff(attempt)).await;
+                    if let Some(resource) = resource {
+                        self.throttle(resource).await?;
+                    }
+                    attempt += 1;
+                    continue;
+                }
+                Some(_) => DeconzError::RetriesExhausted {
+                    attempts: attempt,
+                    source: Box::new(err),
+                },
+                None if attempt > 1 => DeconzError::FailedAfterRetry {
+                    attempts: attempt,
+                    source: Box::new(err),
+                },
+                None => err,
+            };
+            self.retry_counters.record(attempt);
+            return Err(err);
         }
-        match response.json::<Response>().await {
-            Ok(response) => { return Ok(response); }
-            Err(err) => { panic!("Deserializing failed with: {err}") }
+    }
+
+    /// The number of requests and attempts made by this connection so far.
+    pub fn retry_stats(&self) -> RetryStats {
+        self.retry_counters.stats()
+    }
+
+    /// Waits for the rate limiter, if one is configured, before a command is sent to `resource`.
+    async fn throttle(&self, resource: &Resource) -> Result<(), DeconzError> {
+        match &self.rate_limiter {
+            Some(limiter) => limiter.acquire(resource).await,
+            None => Ok(()),
         }
     }
 
-    async fn put_request<T, Response>(&self, url: Url, data: T) -> Result<Response, Box<dyn std::error::Error>>
+    /// Sends a light state to a light or group, going through the rate limiter and coalescer.
+    async fn send_state(
+        &self,
+        resource: Resource,
+        url: Url,
+        state: &LightState,
+    ) -> Result<StateResponse, DeconzError> {
+        match &self.coalescer {
+            Some(coalescer) => {
+                let send = |state: LightState| {
+                    let resource = &resource;
+                    async move { Ok(self.request_with_attempts(Method::PUT, url, Some(&state), Some(resource)).await?.0) }
+                };
+                coalescer.submit(&resource, state, self.throttle(&resource), send).await
+            }
+            None => {
+                self.throttle(&resource).await?;
+                Ok(self.request_with_attempts(Method::PUT, url, Some(state), Some(&resource)).await?.0)
+            }
+        }
+    }
+
+    async fn get_request<Response>(&self, url: Url) -> Result<Response, DeconzError>
+        where
+            Response: DeserializeOwned,
+    {
+        self.request::<(), _>(Method::GET, url, None).await
+    }
+
+    async fn put_request<T, Response>(&self, url: Url, data: T) -> Result<Response, DeconzError>
+        where
+            T: Serialize,
+            Response: DeserializeOwned,
+    {
+        self.request(Method::PUT, url, Some(&data)).await
+    }
+
+    async fn post_request<T, Response>(&self, url: Url, data: T) -> Result<Response, DeconzError>
         where
             T: Serialize,
             Response: DeserializeOwned,
     {
-        let response = match self.client.put(url).json(&data).send().await {
-            Ok(response) => response,
-            Err(err) => return Err(format!("Network error {}", err).into()),
+        self.request(Method::POST, url, Some(&data)).await
+    }
+
+    async fn delete_request<Response>(&self, url: Url) -> Result<Response, DeconzError>
+        where
+            Response: DeserializeOwned,
+    {
+        self.request::<(), _>(Method::DELETE, url, None).await
+    }
+
+    /// Creates a resource with a POST request and returns the id the gateway assigned to it.
+    pub async fn create_resource<T>(&self, path: &str, data: &T) -> Result<String, DeconzError>
+        where
+            T: Serialize + ?Sized,
+    {
+        let url = self.resource_url(path)?;
+        let response = self.post_request(url, data).await?;
+        Ok(first_success::<ResourceId>(response)?.id)
+    }
+
+    /// Deletes a resource and returns the gateway's confirmation.
+    pub async fn delete_resource(&self, path: &str) -> Result<DeleteConfirmation, DeconzError> {
+        let url = self.resource_url(path)?;
+        first_success(self.delete_request(url).await?)
+    }
+
+    /// Resolves a resource path like `lights/1/state` against `api/<key>/`.
+    fn resource_url(&self, path: &str) -> Result<Url, DeconzError> {
+        let url = self.api_url.join(path.trim_start_matches('/'))?;
+        if !url.as_str().starts_with(self.api_url.as_str()) {
+            return Err(DeconzError::InvalidUrl {
+                url: path.to_string(),
+                reason: "resource path must be relative to the api key",
+            });
+        }
+        Ok(url)
+    }
+
+    /// Sends a GET request for a resource path relative to `api/<key>/`.
+    ///
+    /// Use [`serde_json::Value`] as `Response` for endpoints without a typed response.
+    ///
+    /// ```no_run
+    /// # async fn example(connection: deconz_rs::connection::DeconzConnection) -> Result<(), deconz_rs::connection::DeconzError> {
+    /// let sensors: serde_json::Value = connection.get("sensors").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get<Response>(&self, path: &str) -> Result<Response, DeconzError>
+        where
+            Response: DeserializeOwned,
+    {
+        let url = self.resource_url(path)?;
+        self.request::<(), _>(Method::GET, url, None).await
+    }
+
+    /// Like [`Self::get`], but also returns how many attempts the request took.
+    pub async fn get_with_attempts<Response>(&self, path: &str) -> Result<(Response, u32), DeconzError>
+        where
+            Response: DeserializeOwned,
+    {
+        let url = self.resource_url(path)?;
+        self.request_with_attempts::<(), _>(Method::GET, url, None, None).await
+    }
+
+    /// Sends a PUT request for a resource path relative to `api/<key>/`.
+    pub async fn put<T, Response>(&self, path: &str, data: &T) -> Result<Response, DeconzError>
+        where
+            T: Serialize + ?Sized,
+            Response: DeserializeOwned,
+    {
+        let url = self.resource_url(path)?;
+        self.request(Method::PUT, url, Some(data)).await
+    }
+
+    /// Sends a POST request for a resource path relative to `api/<key>/`.
+    pub async fn post<T, Response>(&self, path: &str, data: &T) -> Result<Response, DeconzError>
+        where
+            T: Serialize + ?Sized,
+            Response: DeserializeOwned,
+    {
+        let url = self.resource_url(path)?;
+        self.post_request(url, data).await
+    }
+
+    /// Sends a DELETE request for a resource path relative to `api/<key>/`.
+    pub async fn delete<Response>(&self, path: &str) -> Result<Response, DeconzError>
+        where
+            Response: DeserializeOwned,
+    {
+        let url = self.resource_url(path)?;
+        self.delete_request(url).await
+    }
+
+    pub async fn get_config(&self) -> Result<GatewayConfig, DeconzError> {
+        let url = self.api_url.join("config")?;
+        let config: GatewayConfig = self.get_request(url).await?;
+        if let Ok(version) = GatewayVersion::from_config(&config) {
+            *self.version_cache().lock().unwrap() = Some(version);
+        }
+        Ok(config)
+    }
+
+    /// Changes the gateway configuration after validating `update`, returning the result per field.
+    pub async fn modify_config(&self, update: &ConfigUpdate) -> Result<FieldResults, DeconzError> {
+        update.validate()?;
+        if update.websocket_notify_all.is_some() {
+            self.require_sw_version(WEBSOCKET_NOTIFY_ALL_SW_VERSION, "websocketnotifyall").await?;
+        }
+        let url = self.api_url.join("config")?;
+        field_results(self.put_request(url, update).await, "/config")
+    }
+
+    /// Lists the api keys known to the gateway.
+    pub async fn list_api_keys(&self) -> Result<HashMap<String, WhitelistEntry>, DeconzError> {
+        Ok(self.get_config().await?.whitelist)
+    }
+
+    pub async fn delete_api_key(&self, api_key: &str) -> Result<DeleteConfirmation, DeconzError> {
+        validate_api_key(api_key)?;
+        self.delete_resource(&format!("config/whitelist/{api_key}")).await
+    }
+
+    /// Deletes all api keys that were not used for longer than `max_age`, except the key of this
+    /// connection, and returns the result for each stale key, sorted by key.
+    ///
+    /// Ages are measured against the gateway clock. A key that can't be deleted doesn't stop the
+    /// others from being deleted.
+    pub async fn revoke_stale_api_keys(
+        &self,
+        max_age: Duration,
+    ) -> Result<Vec<(String, Result<DeleteConfirmation, DeconzError>)>, DeconzError> {
+        let config = self.get_config().await?;
+        let max_age = match chrono::Duration::from_std(max_age) {
+            Ok(max_age) => max_age,
+            Err(_) => return Ok(vec![]),
         };
-        match response.json::<Response>().await {
-            Ok(response) => { Ok(response) }
-            Err(err) => { panic!("Deserializing failed with error: {err}") }
+        let mut stale: Vec<String> = config
+            .whitelist
+            .into_iter()
+            .filter(|(api_key, entry)| *api_key != self.api_key && config.utc - entry.last_active() > max_age)
+            .map(|(api_key, _)| api_key)
+            .collect();
+        stale.sort();
+
+        let mut results = Vec::with_capacity(stale.len());
+        for api_key in stale {
+            let result = self.delete_api_key(&api_key).await;
+            results.push((api_key, result));
         }
+        Ok(results)
     }
 
-    pub async fn get_all_lights(&self) -> Result<HashMap<String, Light>, Box<dyn std::error::Error>> {
-        let url = self.api_url.join("lights").unwrap();
-        self.get_request(url).await
+    /// Changes the gateway password, e.g. the default `delight` password after installation.
+    pub async fn change_password(&self, change: &PasswordChange) -> Result<(), DeconzError> {
+        let url = self.api_url.join("config/password")?;
+        self.put_request::<_, serde_json::Value>(url, change).await?;
+        Ok(())
     }
 
-    pub async fn get_light_state(&self, id: &str) -> Result<LightState, Box<dyn std::error::Error>> {
-        let url = self
-            .api_url
-            .join("lights/").expect("failed to build url")
-            .join(id).expect("failed to build url");
-        self.get_request(url).await
+    /// Resets username and password to the defaults (`delight`/`delight`).
+    ///
+    /// The gateway only accepts this within 10 minutes after it was started.
+    pub async fn reset_password(&self) -> Result<(), DeconzError> {
+        let url = self.url.join("api/config/password")?;
+        self.delete_request::<serde_json::Value>(url).await?;
+        Ok(())
+    }
+
+    /// Resets the gateway to factory defaults.
+    ///
+    /// `confirmation` has to name the bridge id of this gateway, otherwise nothing is sent.
+    pub async fn factory_reset(
+        &self,
+        reset: FactoryReset,
+        confirmation: &ResetConfirmation,
+    ) -> Result<FactoryResetResult, DeconzError> {
+        let bridge_id = self.get_config().await?.bridge_id;
+        if !bridge_id.eq_ignore_ascii_case(confirmation.bridge_id()) {
+            return Err(DeconzError::ResetNotConfirmed {
+                confirmed: confirmation.bridge_id().to_string(),
+                actual: bridge_id,
+            });
+        }
+
+        let url = self.api_url.join("config/reset")?;
+        self.post_request::<_, serde_json::Value>(url, reset).await?;
+        Ok(FactoryResetResult { bridge_id, reset })
+    }
+
+    /// Fetches config, lights, groups, sensors, scenes, schedules and rules in one request.
+    pub async fn get_full_state(&self) -> Result<FullState, DeconzError> {
+        self.get_request(self.api_url.clone()).await
+    }
+
+    pub async fn get_all_lights(&self) -> Result<HashMap<String, Light>, DeconzError> {
+        let url = self.api_url.join("lights")?;
+        let lights: HashMap<String, Light> = self.get_request(url).await?;
+        *self.known_lights.lock().unwrap() = lights.clone();
+        Ok(lights)
+    }
+
+    pub async fn get_light(&self, id: &str) -> Result<Light, DeconzError> {
+        let light: Light = self.get(&format!("lights/{id}")).await?;
+        self.known_lights.lock().unwrap().insert(id.to_string(), light.clone());
+        Ok(light)
+    }
+
+    pub async fn get_light_state(&self, id: &str) -> Result<LightState, DeconzError> {
+        Ok(self.get_light(id).await?.state)
+    }
+
+    /// Deletes a light. With `reset` the light is also reset to factory settings, so it can
+    /// join another network.
+    pub async fn delete_light(&self, id: &str, reset: bool) -> Result<DeleteConfirmation, DeconzError> {
+        let url = self.resource_url(&format!("lights/{id}"))?;
+        let response = self.request(Method::DELETE, url, Some(&json!({ "reset": reset }))).await?;
+        self.known_lights.lock().unwrap().remove(id);
+        first_success(response)
     }
 
+    pub async fn remove_light_from_all_groups(&self, id: &str) -> Result<DeleteConfirmation, DeconzError> {
+        self.delete_resource(&format!("lights/{id}/groups")).await
+    }
+
+    pub async fn remove_light_from_all_scenes(&self, id: &str) -> Result<DeleteConfirmation, DeconzError> {
+        self.delete_resource(&format!("lights/{id}/scenes")).await
+    }
+
+    /// Sends a new state to a light. Unless disabled with
+    /// [`DeconzConnectionBuilder::validate_light_state`], the state is validated first, against
+    /// the light itself if it was fetched before, otherwise only its ranges.
     pub async fn set_light_state(
         &self,
         id: &str,
         new_state: &LightState,
-    ) -> Result<Vec<RequestResponse<HashMap<String, serde_json::Value>>>, Box<dyn std::error::Error>> {
+    ) -> Result<LightStateResult, DeconzError> {
+        if self.validate_light_state {
+            match self.known_lights.lock().unwrap().get(id) {
+                Some(light) => new_state.validate(light)?,
+                None => new_state.validate_ranges()?,
+            }
+        }
         let url = self
             .api_url
-            .join("lights/").expect("failed to build url")
-            .join(format!("{id}/").as_str()).expect("failed to build url")
-            .join("state").expect("failed to build url");
+            .join("lights/")?
+            .join(format!("{id}/").as_str())?
+            .join("state")?;
 
-        self.put_request(url, new_state).await
+        let response = self.send_state(Resource::Light(id.to_string()), url, new_state).await;
+        LightStateResult::from_response(response, &format!("/lights/{id}/state"))
     }
 
-    pub async fn set_light_attributes(
+    pub async fn set_group_state(
         &self,
         id: &str,
-        attrs: &LightAttributes,
-    ) -> Result<Vec<RequestResponse<HashMap<String, serde_json::Value>>>, Box<dyn std::error::Error>> {
+        new_state: &LightState,
+    ) -> Result<Vec<RequestResponse<HashMap<String, serde_json::Value>>>, DeconzError> {
         let url = self
             .api_url
-            .join("lights/").expect("failed to build url")
-            .join(format!("{id}").as_str()).expect("failed to build url");
-        self.put_request(url, attrs).await
+            .join("groups/")?
+            .join(format!("{id}/").as_str())?
+            .join("action")?;
+
+        self.send_state(Resource::Group(id.to_string()), url, new_state).await
+    }
+
+    /// Creates a group and returns its id.
+    pub async fn create_group(&self, group: &CreateGroupRequest) -> Result<String, DeconzError> {
+        self.create_resource("groups", group).await
+    }
+
+    pub async fn delete_group(&self, id: &str) -> Result<DeleteConfirmation, DeconzError> {
+        self.delete_resource(&format!("groups/{id}")).await
+    }
+
+    /// Renames a light, returning the result per field.
+    pub async fn set_light_attributes(&self, id: &str, attrs: &LightAttributes) -> Result<FieldResults, DeconzError> {
+        let url = self.resource_url(&format!("lights/{id}"))?;
+        field_results(self.put_request(url, attrs).await, &format!("/lights/{id}"))
     }
-}
 
-#[derive(Serialize, Deserialize)]
-pub struct LightAttributes {
-    name: String,
+    /// Changes the settings stored in a light, returning the result per field keyed by its path
+    /// below `config`, e.g. `bri/startup`.
+    pub async fn set_light_config(&self, id: &str, config: &LightConfig) -> Result<FieldResults, DeconzError> {
+        let url = self.resource_url(&format!("lights/{id}/config"))?;
+        field_results(self.put_request(url, config).await, &format!("/lights/{id}/config"))
+    }
 }
 
 
+#[cfg(test)]
+mod connection_tests {
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
+
+    use httpmock::prelude::*;
+    use serde_json::json;
+    use url::Url;
+
+    use crate::connection::{probe, DeconzConnection, DeconzConnectionBuilder, DeconzError, DeconzErrorType, DeleteConfirmation, ResourceId};
+    use crate::endpoints::configuration::{
+        ConfigUpdate, FactoryReset, PasswordChange, ResetConfirmation, TimeFormat, TokenRequest, UpdateState,
+    };
+    use crate::endpoints::groups::CreateGroupRequest;
+    use crate::endpoints::light::{
+        AlertMode, BrightnessConfig, ColorConfig, LightAttributes, LightConfig, LightState, LightStateError, OnConfig, StartupConfig,
+    };
+    use crate::rate_limit::RateLimit;
+    use crate::retry::{RetryPolicy, RetryStats};
+    use crate::test_server::{sequence_server, Reply};
+
+    fn connection(server: &MockServer) -> DeconzConnection {
+        let url = Url::parse(&server.base_url()).unwrap();
+        DeconzConnection::new(url, "KEY".to_string()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_decode_failure_is_an_error() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/lights");
+            then.status(200).body("not json");
+        });
+        let connection = connection(&server);
+
+        match connection.get_all_lights().await {
+            Err(DeconzError::Decode { body, .. }) => assert_eq!(body, "not json"),
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_missing_success_entry_keeps_body() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(DELETE).path("/api/KEY/groups/1");
+            then.status(200).body("[]");
+        });
+        let connection = connection(&server);
+
+        match connection.delete_group("1").await {
+            Err(DeconzError::Decode { body, .. }) => assert_eq!(body, "[]"),
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_all_lights() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/lights");
+            then.status(200).body(include_str!("test-api-responses/get-all-lights.json"));
+        });
+        let connection = connection(&server);
+
+        let lights = connection.get_all_lights().await.unwrap();
+        assert_eq!(lights.len(), 2);
+        assert_eq!(lights["1"].manufacturer_name, "dresden elektronik");
+    }
+
+    #[tokio::test]
+    async fn test_token_request_gateway_error() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST).path("/api");
+            then.status(403).body(r#"[{"error": {"type": 101, "address": "/", "description": "link button not pressed"}}]"#);
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let request = TokenRequest::new("deconz-rs".to_string(), None).unwrap();
+
+        match DeconzConnection::new_without_key(url, request).await {
+            Err(DeconzError::Gateway(err)) => assert_eq!(err.r#type, DeconzErrorType::LinkButtonNotPressed),
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_error_array_on_put() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/1/state");
+            then.status(400).body(include_str!("test-api-responses/set-light-error.json"));
+        });
+        let connection = connection(&server);
+
+        let err = connection.put::<_, serde_json::Value>("lights/1/state", &LightState::default()).await.unwrap_err();
+        assert_eq!(err.error_type(), Some(DeconzErrorType::InvalidValue));
+    }
+
+    #[tokio::test]
+    async fn test_partial_success() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/1/state");
+            then.status(200).body(r#"[
+                {"success": {"/lights/1/state/on": true}},
+                {"error": {"type": 6, "address": "/lights/1/state/hue", "description": "parameter, hue, not available"}}
+            ]"#);
+        });
+        let connection = connection(&server);
+
+        match connection.put::<_, serde_json::Value>("lights/1/state", &LightState::default()).await {
+            Err(DeconzError::PartialSuccess { succeeded, failed }) => {
+                assert_eq!(succeeded.len(), 1);
+                assert_eq!(failed[0].r#type, DeconzErrorType::ParameterNotAvailable);
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_light_state_result() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/1/state").json_body(json!({"on": true, "bri": 0}));
+            then.status(200).body(include_str!("test-api-responses/set-light-success.json"));
+        });
+        server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/2/state");
+            then.status(200).body(r#"[
+                {"success": {"/lights/2/state/on": true}},
+                {"error": {"type": 6, "address": "/lights/2/state/hue", "description": "parameter, hue, not available"}}
+            ]"#);
+        });
+        server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/3/state");
+            then.status(400).body(r#"[{"error": {"type": 7, "address": "/lights/3/state", "description": "invalid value, 1000, for parameter, bri"}}]"#);
+        });
+        let connection = connection(&server);
+
+        let state = LightState { on: Some(true), bri: Some(0), ..Default::default() };
+        let result = connection.set_light_state("1", &state).await.unwrap();
+        assert!(result.is_fully_applied());
+        assert_eq!(result.accepted.bri, Some(0));
+        assert_eq!(result.accepted.ct, Some(454));
+        assert_eq!(result.accepted.alert, Some(AlertMode::None));
+
+        let result = connection.set_light_state("2", &LightState::default()).await.unwrap();
+        assert_eq!(result.accepted.on, Some(true));
+        assert_eq!(result.rejected["hue"].r#type, DeconzErrorType::ParameterNotAvailable);
+
+        let result = connection.set_light_state("3", &LightState::default()).await.unwrap();
+        assert_eq!(result.accepted, LightState::default());
+        assert_eq!(result.rejected["bri"].r#type, DeconzErrorType::InvalidValue);
+    }
+
+    #[tokio::test]
+    async fn test_failed_light_state_request() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/1/state");
+            then.status(403).body(r#"[{"error": {"type": 1, "address": "/", "description": "unauthorized user"}}]"#);
+        });
+        server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/2/state");
+            then.status(503).body(r#"[{"error": {"type": 951, "address": "/lights/2/state", "description": "bridge busy"}}]"#);
+        });
+        server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/9/state");
+            then.status(404).body(r#"[{"error": {"type": 3, "address": "/lights/9", "description": "resource, /lights/9, not available"}}]"#);
+        });
+        let connection = connection(&server);
+        let on = LightState { on: Some(true), ..Default::default() };
+
+        let err = connection.set_light_state("1", &on).await.unwrap_err();
+        assert_eq!(err.error_type(), Some(DeconzErrorType::UnauthorizedUser));
+        let err = connection.set_light_state("2", &on).await.unwrap_err();
+        assert_eq!(err.error_type(), Some(DeconzErrorType::BridgeBusy));
+        let err = connection.set_light_state("9", &on).await.unwrap_err();
+        assert_eq!(err.error_type(), Some(DeconzErrorType::ResourceNotAvailable));
+    }
+
+    #[tokio::test]
+    async fn test_light_state_validation() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/lights");
+            then.status(200).body(include_str!("test-api-responses/get-all-lights.json"));
+        });
+        let put = server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/2/state");
+            then.status(200).body(r#"[{"success": {"/lights/2/state/ct": 300}}]"#);
+        });
+        let connection = connection(&server);
+        connection.get_all_lights().await.unwrap();
+
+        let ct = LightState { ct: Some(300), ..Default::default() };
+        match connection.set_light_state("2", &ct).await {
+            Err(DeconzError::InvalidLightState(LightStateError::Unsupported { field })) => assert_eq!(field, "ct"),
+            other => panic!("unexpected result {other:?}"),
+        }
+        let hue = LightState { hue: Some(70000), ..Default::default() };
+        let err = connection.set_light_state("9", &hue).await.unwrap_err();
+        assert!(matches!(err, DeconzError::InvalidLightState(LightStateError::OutOfRange { field: "hue", .. })));
+        let conflicting = LightState { ct: Some(300), xy: Some([0.3, 0.3]), ..Default::default() };
+        let err = connection.set_light_state("1", &conflicting).await.unwrap_err();
+        assert!(matches!(err, DeconzError::InvalidLightState(LightStateError::ConflictingColorModes { .. })));
+        put.assert_hits(0);
+
+        let unchecked = DeconzConnection::builder(Url::parse(&server.base_url()).unwrap())
+            .api_key("KEY")
+            .validate_light_state(false)
+            .build()
+            .unwrap();
+        unchecked.get_all_lights().await.unwrap();
+        unchecked.set_light_state("2", &ct).await.unwrap();
+        put.assert_hits(1);
+    }
+
+    #[tokio::test]
+    async fn test_send_modified_light_state() {
+        let server = MockServer::start();
+        let lights: serde_json::Value = serde_json::from_str(include_str!("test-api-responses/get-all-lights.json")).unwrap();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/lights/1");
+            then.status(200).json_body(lights["1"].clone());
+        });
+        let put = server.mock(|when, then| {
+            when.method(PUT)
+                .path("/api/KEY/lights/1/state")
+                .json_body(json!({"alert": "none", "bri": 121, "ct": 307, "effect": "none", "on": true}));
+            then.status(200).body(r#"[{"success": {"/lights/1/state/bri": 121}}, {"success": {"/lights/1/state/on": true}}]"#);
+        });
+        let connection = connection(&server);
+
+        let mut light = connection.get_light("1").await.unwrap();
+        assert_eq!((light.state.ct, light.state.xy, light.state.hue), (Some(307), None, None));
+        light.change_brightness(10).on(true);
+        let result = connection.set_light_state("1", &light.state).await.unwrap();
+        assert!(result.is_fully_applied());
+        put.assert();
+    }
+
+    #[tokio::test]
+    async fn test_http_status_without_error_body() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/lights");
+            then.status(404).body("Not Found");
+        });
+        let connection = connection(&server);
+
+        let err = connection.get_all_lights().await.unwrap_err();
+        assert_eq!(err.status(), Some(reqwest::StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_builder_adds_trailing_slash() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/deconz/api/KEY/lights");
+            then.status(200).body(include_str!("test-api-responses/get-all-lights.json"));
+        });
+        let url = Url::parse(&server.url("/deconz")).unwrap();
+        let connection = DeconzConnection::builder(url)
+            .api_key("KEY")
+            .user_agent("deconz-rs-test")
+            .build()
+            .unwrap();
+
+        let _ = connection.get_all_lights().await;
+        mock.assert();
+    }
+
+    #[test]
+    fn test_builder_rejects_invalid_url() {
+        let url = Url::parse("ftp://192.168.0.166/").unwrap();
+        let result = DeconzConnection::builder(url).api_key("KEY").build();
+        assert!(matches!(result, Err(DeconzError::InvalidUrl { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_builder_timeout() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/lights");
+            then.status(200).body("{}").delay(Duration::from_secs(2));
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let connection = DeconzConnection::builder(url)
+            .api_key("KEY")
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        match connection.get_all_lights().await {
+            Err(DeconzError::Network(err)) => assert!(err.is_timeout()),
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_bridge_busy() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/lights");
+            then.status(503).body(r#"[{"error": {"type": 951, "address": "/lights", "description": "bridge busy"}}]"#);
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let policy = RetryPolicy::new(3).with_backoff(Duration::from_millis(1), Duration::from_millis(5));
+        let connection = DeconzConnection::builder(url)
+            .api_key("KEY")
+            .retry_policy(policy)
+            .build()
+            .unwrap();
+
+        match connection.get_all_lights().await {
+            Err(err @ DeconzError::RetriesExhausted { attempts: 3, .. }) => {
+                assert_eq!(err.error_type(), Some(DeconzErrorType::BridgeBusy));
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+        mock.assert_hits(3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_attempts_are_reported() {
+        let busy = || Reply::Json(503, r#"[{"error": {"type": 951, "address": "/lights", "description": "bridge busy"}}]"#.to_string());
+        let (url, server) = sequence_server(vec![
+            busy(),
+            Reply::Json(200, "{}".to_string()),
+            busy(),
+            Reply::Json(404, "Not Found".to_string()),
+        ])
+        .await;
+        let policy = RetryPolicy::new(3).with_backoff(Duration::from_millis(1), Duration::from_millis(5));
+        let connection = DeconzConnection::builder(url).api_key("KEY").retry_policy(policy).build().unwrap();
+
+        let (lights, attempts): (serde_json::Value, u32) = connection.get_with_attempts("lights").await.unwrap();
+        assert_eq!((lights, attempts), (json!({}), 2));
+
+        let err = connection.get_all_lights().await.unwrap_err();
+        assert!(matches!(err, DeconzError::FailedAfterRetry { attempts: 2, .. }));
+        assert_eq!(err.status(), Some(reqwest::StatusCode::NOT_FOUND));
+        assert_eq!(connection.retry_stats(), RetryStats { requests: 2, attempts: 4, retried_requests: 2 });
+        assert_eq!(server.await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_retries_fail_per_field_requests() {
+        let busy = |address: &str| {
+            Reply::Json(503, format!(r#"[{{"error": {{"type": 951, "address": "{address}", "description": "bridge busy"}}}}]"#))
+        };
+        let (url, server) = sequence_server(vec![
+            busy("/lights/1/state"),
+            busy("/lights/1/state"),
+            busy("/config/name"),
+            busy("/config/name"),
+        ])
+        .await;
+        let policy = RetryPolicy::new(2).with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let connection = DeconzConnection::builder(url).api_key("KEY").retry_policy(policy).build().unwrap();
+
+        let on = LightState { on: Some(true), ..Default::default() };
+        let err = connection.set_light_state("1", &on).await.unwrap_err();
+        assert!(matches!(err, DeconzError::RetriesExhausted { attempts: 2, .. }), "{err:?}");
+        assert_eq!(err.error_type(), Some(DeconzErrorType::BridgeBusy));
+        let update = ConfigUpdate { name: Some("gw".to_string()), ..Default::default() };
+        let err = connection.modify_config(&update).await.unwrap_err();
+        assert_eq!(err.attempts(), Some(2));
+        assert_eq!(server.await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_group_commands() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/groups/1/action");
+            then.status(200).body(include_str!("test-api-responses/set-group-state.json"));
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let connection = DeconzConnection::builder(url)
+            .api_key("KEY")
+            .rate_limit(RateLimit::new(100.0, 10.0))
+            .build()
+            .unwrap();
+        let state = LightState::default();
+        let start = Instant::now();
+
+        let (a, b) = tokio::join!(connection.set_group_state("1", &state), connection.set_group_state("1", &state));
+        assert!(a.is_ok() && b.is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        mock.assert_hits(2);
+    }
+
+    #[tokio::test]
+    async fn test_retries_are_rate_limited() {
+        let (url, server) = sequence_server(vec![
+            Reply::Json(503, r#"[{"error": {"type": 951, "address": "/lights/1/state", "description": "bridge busy"}}]"#.to_string()),
+            Reply::Json(200, r#"[{"success": {"/lights/1/state/on": true}}]"#.to_string()),
+        ])
+        .await;
+        let connection = DeconzConnection::builder(url)
+            .api_key("KEY")
+            .rate_limit(RateLimit::new(10.0, 1.0))
+            .retry_policy(RetryPolicy::new(2).with_backoff(Duration::from_millis(1), Duration::from_millis(1)))
+            .build()
+            .unwrap();
+        let start = Instant::now();
+
+        let on = LightState { on: Some(true), ..Default::default() };
+        let result = connection.set_light_state("1", &on).await.unwrap();
+        assert_eq!(result.accepted.on, Some(true));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(server.await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_light_states() {
+        let server = MockServer::start();
+        let first = server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/1/state").json_body(json!({"on": true}));
+            then.status(200).body(r#"[{"success": {"/lights/1/state/on": true}}]"#);
+        });
+        let merged = server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/1/state").json_body(json!({"bri": 3, "ct": 300}));
+            then.status(200).body(r#"[{"success": {"/lights/1/state/bri": 3}}, {"success": {"/lights/1/state/ct": 300}}]"#);
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let connection = DeconzConnection::builder(url)
+            .api_key("KEY")
+            .rate_limit(RateLimit::new(10.0, 1.0))
+            .coalesce_commands(true)
+            .build()
+            .unwrap();
+        let state = |bri, ct, on| LightState { bri, ct, on, ..Default::default() };
+        let states = [
+            state(None, None, Some(true)),
+            state(Some(1), None, None),
+            state(Some(2), Some(300), None),
+            state(Some(3), None, None),
+        ];
+
+        let (a, b, c, d) = tokio::join!(
+            connection.set_light_state("1", &states[0]),
+            connection.set_light_state("1", &states[1]),
+            connection.set_light_state("1", &states[2]),
+            connection.set_light_state("1", &states[3]),
+        );
+        assert_eq!(a.unwrap().accepted.on, Some(true));
+        for result in [b, c, d] {
+            assert_eq!(result.unwrap().accepted, state(Some(3), Some(300), None));
+        }
+        first.assert();
+        merged.assert();
+    }
+
+    #[tokio::test]
+    async fn test_raw_requests() {
+        let server = MockServer::start();
+        let get = server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/groups");
+            then.status(200).body(include_str!("test-api-responses/get-all-groups.json"));
+        });
+        let delete = server.mock(|when, then| {
+            when.method(DELETE).path("/api/KEY/groups/1");
+            then.status(200).body(include_str!("test-api-responses/delete-group.json"));
+        });
+        let connection = connection(&server);
+
+        let groups: serde_json::Value = connection.get("/groups").await.unwrap();
+        assert_eq!(groups["2"]["name"], "Kitchen");
+        let deleted: serde_json::Value = connection.delete("groups/1").await.unwrap();
+        assert_eq!(deleted[0]["success"]["id"], "1");
+        get.assert();
+        delete.assert();
+    }
+
+    #[tokio::test]
+    async fn test_raw_request_stays_below_api_key() {
+        let server = MockServer::start();
+        let connection = connection(&server);
+
+        let result = connection.get::<serde_json::Value>("http://example.com/lights").await;
+        assert!(matches!(result, Err(DeconzError::InvalidUrl { .. })));
+        let result = connection.get::<serde_json::Value>("../OTHER/lights").await;
+        assert!(matches!(result, Err(DeconzError::InvalidUrl { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_create_and_delete_group() {
+        let server = MockServer::start();
+        let create = server.mock(|when, then| {
+            when.method(POST).path("/api/KEY/groups").json_body(json!({"name": "Kitchen"}));
+            then.status(200).body(include_str!("test-api-responses/create-group.json"));
+        });
+        let delete = server.mock(|when, then| {
+            when.method(DELETE).path("/api/KEY/groups/1");
+            then.status(200).body(include_str!("test-api-responses/delete-group.json"));
+        });
+        let connection = connection(&server);
+
+        let group = CreateGroupRequest { name: "Kitchen".to_string() };
+        assert_eq!(connection.create_group(&group).await.unwrap(), "3");
+        let deleted = connection.delete_group("1").await.unwrap();
+        assert_eq!(deleted, DeleteConfirmation::Id(ResourceId { id: "1".to_string() }));
+        create.assert();
+        delete.assert();
+    }
+
+    #[tokio::test]
+    async fn test_light_lifecycle() {
+        let server = MockServer::start();
+        let lights: serde_json::Value = serde_json::from_str(include_str!("test-api-responses/get-all-lights.json")).unwrap();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/lights/1");
+            then.status(200).json_body(lights["1"].clone());
+        });
+        let rename = server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/1").json_body(json!({"name": "Desk"}));
+            then.status(200).json_body(json!([{"success": {"/lights/1/name": "Desk"}}]));
+        });
+        let groups = server.mock(|when, then| {
+            when.method(DELETE).path("/api/KEY/lights/1/groups");
+            then.status(200).json_body(json!([{"success": {"id": "1"}}]));
+        });
+        let scenes = server.mock(|when, then| {
+            when.method(DELETE).path("/api/KEY/lights/1/scenes");
+            then.status(200).json_body(json!([{"success": {"id": "1"}}]));
+        });
+        let delete = server.mock(|when, then| {
+            when.method(DELETE).path("/api/KEY/lights/1").json_body(json!({"reset": true}));
+            then.status(200).json_body(json!([{"success": {"id": "1"}}]));
+        });
+        let connection = connection(&server);
+
+        let light = connection.get_light("1").await.unwrap();
+        assert_eq!(light.name, "Light 1");
+        assert_eq!(connection.get_light_state("1").await.unwrap(), light.state);
+
+        let attributes = LightAttributes { name: Some("Desk".to_string()) };
+        let renamed = connection.set_light_attributes("1", &attributes).await.unwrap();
+        assert_eq!(renamed["name"], Ok(json!("Desk")));
+        connection.remove_light_from_all_groups("1").await.unwrap();
+        connection.remove_light_from_all_scenes("1").await.unwrap();
+        let deleted = connection.delete_light("1", true).await.unwrap();
+        assert_eq!(deleted, DeleteConfirmation::Id(ResourceId { id: "1".to_string() }));
+        rename.assert();
+        groups.assert();
+        scenes.assert();
+        delete.assert();
+    }
+
+    #[tokio::test]
+    async fn test_set_light_config() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(PUT)
+                .path("/api/KEY/lights/1/config")
+                .json_body(json!({"bri": {"startup": 254}, "color": {"ct": {"startup": 366}}, "on": {"startup": true}}));
+            then.status(200).body(r#"[
+                {"success": {"/lights/1/config/bri/startup": 254}},
+                {"success": {"/lights/1/config/color/ct/startup": 366}},
+                {"error": {"type": 6, "address": "/lights/1/config/on/startup", "description": "parameter, on/startup, not available"}}
+            ]"#);
+        });
+        let connection = connection(&server);
+
+        let config = LightConfig {
+            bri: Some(BrightnessConfig { startup: Some(254), ..Default::default() }),
+            color: Some(ColorConfig { ct: Some(StartupConfig { startup: Some(366) }), ..Default::default() }),
+            on: Some(OnConfig { startup: Some(true) }),
+        };
+        let results = connection.set_light_config("1", &config).await.unwrap();
+        assert_eq!(results["bri/startup"], Ok(json!(254)));
+        assert_eq!(results["color/ct/startup"], Ok(json!(366)));
+        assert_eq!(results["on/startup"].as_ref().unwrap_err().r#type, DeconzErrorType::ParameterNotAvailable);
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_failed_light_attributes_and_config() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/9");
+            then.status(404).body(r#"[{"error": {"type": 3, "address": "/lights/9", "description": "resource, /lights/9, not available"}}]"#);
+        });
+        server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/lights/1/config");
+            then.status(403).body(r#"[{"error": {"type": 1, "address": "/", "description": "unauthorized user"}}]"#);
+        });
+        let connection = connection(&server);
+
+        let attributes = LightAttributes { name: Some("Desk".to_string()) };
+        let err = connection.set_light_attributes("9", &attributes).await.unwrap_err();
+        assert_eq!(err.error_type(), Some(DeconzErrorType::ResourceNotAvailable));
+        let config = LightConfig { on: Some(OnConfig { startup: Some(true) }), ..Default::default() };
+        let err = connection.set_light_config("1", &config).await.unwrap_err();
+        assert_eq!(err.error_type(), Some(DeconzErrorType::UnauthorizedUser));
+    }
+
+    #[tokio::test]
+    async fn test_delete_with_message_confirmation() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(DELETE).path("/api/KEY/lights/1");
+            then.status(200).body(r#"[{"success": "/lights/1 deleted"}]"#);
+        });
+        let connection = connection(&server);
+
+        let deleted = connection.delete_resource("lights/1").await.unwrap();
+        assert_eq!(deleted, DeleteConfirmation::Message("/lights/1 deleted".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_get_config() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/config");
+            then.status(200).body(include_str!("test-api-responses/get-configuration.json"));
+        });
+        let connection = connection(&server);
+
+        let config = connection.get_config().await.unwrap();
+        assert_eq!(config.bridge_id, "00212EFFFF00C5FB");
+        assert_eq!(config.ip_address, Ipv4Addr::new(192, 168, 80, 142));
+        assert_eq!(config.websocket_port, 23765);
+        assert_eq!(config.time_format, TimeFormat::TwelveHour);
+        assert_eq!(config.utc.to_string(), "2020-06-29 12:00:40");
+        assert_eq!(config.sw_update.unwrap().update_state, UpdateState::NoUpdate);
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/config");
+            then.status(200).json_body(json!({
+                "apiversion": "1.16.0",
+                "bridgeid": "00212EFFFF00C5FB",
+                "datastoreversion": "60",
+                "devicename": "ConBee II",
+                "factorynew": false,
+                "mac": "00:21:2e:ff:ff:00:c5:fb",
+                "modelid": "deCONZ",
+                "name": "Phoscon-GW",
+                "replacesbridgeid": null,
+                "starterkitid": "",
+                "swversion": "2.6.0"
+            }));
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+
+        let info = probe(url).await.unwrap();
+        assert_eq!(info.bridge_id, "00212EFFFF00C5FB");
+        assert_eq!(info.model_id, "deCONZ");
+        assert_eq!(info.api_version, "1.16.0");
+    }
+
+    #[tokio::test]
+    async fn test_probe_times_out() {
+        // Accepts the connection but never answers.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let server = tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+
+        let started = Instant::now();
+        let result = DeconzConnectionBuilder::new(url).timeout(Duration::from_millis(200)).probe().await;
+        match result {
+            Err(DeconzError::Network(err)) => assert!(err.is_timeout()),
+            other => panic!("unexpected result {other:?}"),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_modify_config() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/config").json_body(json!({"name": "gw", "zigbeechannel": 25}));
+            then.status(200).body(r#"[
+                {"success": {"/config/zigbeechannel": 25}},
+                {"error": {"type": 8, "address": "/config/name", "description": "parameter, name, is not modifiable"}}
+            ]"#);
+        });
+        let connection = connection(&server);
+
+        let update = ConfigUpdate {
+            name: Some("gw".to_string()),
+            zigbee_channel: Some(25),
+            ..Default::default()
+        };
+        let results = connection.modify_config(&update).await.unwrap();
+        assert_eq!(results["zigbeechannel"], Ok(json!(25)));
+        assert_eq!(results["name"].as_ref().unwrap_err().r#type, DeconzErrorType::ParameterNotModifiable);
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_modify_config_unauthorized() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/config");
+            then.status(403).body(r#"[{"error": {"type": 1, "address": "/", "description": "unauthorized user"}}]"#);
+        });
+        let connection = connection(&server);
+
+        let update = ConfigUpdate { name: Some("gw".to_string()), ..Default::default() };
+        match connection.modify_config(&update).await {
+            Err(DeconzError::Gateway(err)) => assert_eq!(err.r#type, DeconzErrorType::UnauthorizedUser),
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_modify_config_validates_channel() {
+        let server = MockServer::start();
+        let connection = connection(&server);
+
+        let update = ConfigUpdate {
+            zigbee_channel: Some(12),
+            ..Default::default()
+        };
+        let result = connection.modify_config(&update).await;
+        assert!(matches!(result, Err(DeconzError::InvalidParameter { parameter: "zigbeechannel", .. })));
+    }
+
+    #[tokio::test]
+    async fn test_revoke_stale_api_keys() {
+        let server = MockServer::start();
+        let mut config: serde_json::Value =
+            serde_json::from_str(include_str!("test-api-responses/get-configuration.json")).unwrap();
+        config["whitelist"] = json!({
+            "KEY": {"create date": "2019-01-01T10:00:00", "last use date": "2019-01-01T10:00:00", "name": "this"},
+            "STALE": {"create date": "2020-01-01T10:00:00", "last use date": "2020-01-02T10:00:00", "name": "old test"},
+            "UNUSED": {"create date": "2020-02-01T10:00:00", "name": "never used"},
+            "FRESH": {"create date": "2020-01-01T10:00:00", "last use date": "2020-06-29T11:00:00", "name": "app"}
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/config");
+            then.status(200).json_body(config);
+        });
+        let stale = server.mock(|when, then| {
+            when.method(DELETE).path("/api/KEY/config/whitelist/STALE");
+            then.status(200).body(r#"[{"success": "/config/whitelist/STALE deleted."}]"#);
+        });
+        let unused = server.mock(|when, then| {
+            when.method(DELETE).path("/api/KEY/config/whitelist/UNUSED");
+            then.status(404).body(r#"[{"error": {"type": 3, "address": "/config/whitelist/UNUSED", "description": "resource, /config/whitelist/UNUSED, not available"}}]"#);
+        });
+        let connection = connection(&server);
+
+        assert_eq!(connection.list_api_keys().await.unwrap().len(), 4);
+        let revoked = connection.revoke_stale_api_keys(Duration::from_secs(30 * 24 * 3600)).await.unwrap();
+        let keys: Vec<&str> = revoked.iter().map(|(api_key, _)| api_key.as_str()).collect();
+        assert_eq!(keys, vec!["STALE", "UNUSED"]);
+        assert!(revoked[0].1.is_ok());
+        assert_eq!(revoked[1].1.as_ref().unwrap_err().error_type(), Some(DeconzErrorType::ResourceNotAvailable));
+        stale.assert();
+        unused.assert();
+    }
+
+    #[tokio::test]
+    async fn test_change_and_reset_password() {
+        let server = MockServer::start();
+        let change = server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/config/password").json_body(json!({
+                "username": "delight",
+                "oldhash": "ZGVsaWdodDpkZWxpZ2h0",
+                "newhash": "ZGVsaWdodDpzZWNyZXQ="
+            }));
+            then.status(200).body(include_str!("test-api-responses/change-password.json"));
+        });
+        let reset = server.mock(|when, then| {
+            when.method(DELETE).path("/api/config/password");
+            then.status(200).body(include_str!("test-api-responses/reset-password.json"));
+        });
+        let connection = connection(&server);
+
+        let password = PasswordChange::new("delight", "delight", "secret");
+        connection.change_password(&password).await.unwrap();
+        connection.reset_password().await.unwrap();
+        change.assert();
+        reset.assert();
+    }
+
+    #[tokio::test]
+    async fn test_factory_reset_requires_confirmation() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/config");
+            then.status(200).body(include_str!("test-api-responses/get-configuration.json"));
+        });
+        let reset = server.mock(|when, then| {
+            when.method(POST).path("/api/KEY/config/reset").json_body(json!({"resetGW": false, "deleteDB": true}));
+            then.status(200).body(include_str!("test-api-responses/reset-gateway.json"));
+        });
+        let connection = connection(&server);
+        let options = FactoryReset {
+            reset_network: false,
+            delete_database: true,
+        };
+
+        let result = connection.factory_reset(options, &ResetConfirmation::for_bridge("00212EFFFF000000")).await;
+        assert!(matches!(result, Err(DeconzError::ResetNotConfirmed { .. })));
+        reset.assert_hits(0);
+
+        let result = connection.factory_reset(options, &ResetConfirmation::for_bridge("00212EFFFF00C5FB")).await.unwrap();
+        assert_eq!(result.bridge_id, "00212EFFFF00C5FB");
+        reset.assert_hits(1);
+    }
+
+    #[tokio::test]
+    async fn test_get_full_state_keeps_unparsable_resources() {
+        let server = MockServer::start();
+        let config: serde_json::Value =
+            serde_json::from_str(include_str!("test-api-responses/get-configuration.json")).unwrap();
+        let light = json!({
+            "etag": "026bcfe544ad76c7534e5ca8ed39047c",
+            "hascolor": false,
+            "manufacturername": "dresden elektronik",
+            "modelid": "FLS-PP3 White",
+            "name": "Light 2",
+            "state": {"alert": "none", "bri": 1, "on": false},
+            "swversion": "020C.201000A0",
+            "type": "Dimmable light",
+            "uniqueid": "00:21:2E:FF:FF:00:73:9F-0B"
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/");
+            then.status(200).json_body(json!({
+                "config": config,
+                "lights": {"1": light, "2": {"name": "odd device"}},
+                "groups": {},
+                "sensors": {"1": {"name": "Daylight"}},
+                "schedules": {"1": {
+                    "autodelete": false,
+                    "command": {"address": "/api/KEY/groups/1/action", "body": {"on": true}, "method": "PUT"},
+                    "description": "",
+                    "etag": "6d4b1e8d4a1c1a6b5e0f0c8a9b7d6e5f",
+                    "name": "Wake up",
+                    "status": "enabled",
+                    "time": "W124/T06:30:00"
+                }},
+                "rules": {"1": {
+                    "actions": [{"address": "/groups/1/action", "body": {"on": true}, "method": "PUT"}],
+                    "conditions": [{"address": "/sensors/2/state/buttonevent", "operator": "eq", "value": "1002"}],
+                    "created": "2020-06-01T18:01:02",
+                    "etag": "f3a4c1e57a4b7b2c9e2d6a1b0c8d7e6f",
+                    "lasttriggered": "none",
+                    "name": "Switch on",
+                    "owner": "KEY",
+                    "periodic": 0,
+                    "status": "enabled",
+                    "timestriggered": 0
+                }}
+            }));
+        });
+        let connection = connection(&server);
+
+        let state = connection.get_full_state().await.unwrap();
+        assert_eq!(state.config.parsed().unwrap().bridge_id, "00212EFFFF00C5FB");
+        assert_eq!(state.lights["1"].parsed().unwrap().name, "Light 2");
+        assert!(state.lights["2"].is_raw());
+        assert_eq!(state.sensors.len(), 1);
+        assert_eq!(state.schedules["1"]["time"], "W124/T06:30:00");
+        assert_eq!(state.rules["1"]["timestriggered"], 0);
+        assert_eq!(state.rules["1"]["conditions"][0]["operator"], "eq");
+    }
+
+    #[tokio::test]
+    async fn test_get_full_state_keeps_unparsable_config() {
+        let server = MockServer::start();
+        let mut config: serde_json::Value =
+            serde_json::from_str(include_str!("test-api-responses/get-configuration.json")).unwrap();
+        let config = config.as_object_mut().unwrap();
+        config.remove("netmask");
+        config.remove("whitelist");
+        config.insert("timeformat".to_string(), json!("unknown"));
+        let lights: serde_json::Value = serde_json::from_str(include_str!("test-api-responses/get-all-lights.json")).unwrap();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/");
+            then.status(200).json_body(json!({"config": config, "lights": lights}));
+        });
+        let connection = connection(&server);
+
+        let state = connection.get_full_state().await.unwrap();
+        assert!(state.config.is_raw());
+        assert_eq!(state.lights.len(), 2);
+        assert!(state.lights.values().all(|light| !light.is_raw()));
+    }
+}
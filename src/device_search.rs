@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::time::Instant;
+
+use crate::connection::{DeconzConnection, DeconzError};
+use crate::endpoints::configuration::ConfigUpdate;
+
+/// How long the Zigbee network stays open and how often new devices are polled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceSearchOptions {
+    /// At most 255 seconds, the limit of `permitjoin`.
+    pub duration: Duration,
+    pub poll_interval: Duration,
+}
+
+impl Default for DeviceSearchOptions {
+    fn default() -> Self {
+        DeviceSearchOptions {
+            duration: Duration::from_secs(60),
+            poll_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceKind {
+    Light,
+    Sensor,
+}
+
+impl DeviceKind {
+    fn path(&self) -> &'static str {
+        match self {
+            DeviceKind::Light => "lights",
+            DeviceKind::Sensor => "sensors",
+        }
+    }
+}
+
+/// A device that joined the network during a search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoinedDevice {
+    pub kind: DeviceKind,
+    pub id: String,
+    pub name: String,
+    pub unique_id: Option<String>,
+}
+
+impl DeconzConnection {
+    /// Opens the Zigbee network, searches for new lights and sensors and returns every device
+    /// that joined before `options.duration` passed.
+    pub async fn search_new_devices(&self, options: DeviceSearchOptions) -> Result<Vec<JoinedDevice>, DeconzError> {
+        let seconds = options.duration.as_secs();
+        if !(1..=255).contains(&seconds) {
+            return Err(DeconzError::InvalidParameter {
+                parameter: "permitjoin",
+                reason: format!("must be between 1 and 255 seconds, is {seconds}"),
+            });
+        }
+        let permit_join = ConfigUpdate {
+            permit_join: Some(seconds as u8),
+            ..Default::default()
+        };
+        self.put::<_, serde_json::Value>("config", &permit_join).await?;
+        for kind in [DeviceKind::Light, DeviceKind::Sensor] {
+            self.post::<_, serde_json::Value>(kind.path(), &json!({})).await?;
+        }
+
+        let deadline = Instant::now() + options.duration;
+        let mut found: HashMap<(DeviceKind, String), String> = HashMap::new();
+        loop {
+            for kind in [DeviceKind::Light, DeviceKind::Sensor] {
+                let new: HashMap<String, serde_json::Value> = self.get(&format!("{}/new", kind.path())).await?;
+                for (id, device) in new {
+                    if id == "lastscan" {
+                        continue;
+                    }
+                    let name = device["name"].as_str().unwrap_or_default().to_string();
+                    found.entry((kind, id)).or_insert(name);
+                }
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            let remaining = deadline - Instant::now();
+            tokio::time::sleep(options.poll_interval.min(remaining)).await;
+        }
+
+        let mut devices = Vec::with_capacity(found.len());
+        for ((kind, id), name) in found {
+            let device: serde_json::Value = self.get(&format!("{}/{id}", kind.path())).await?;
+            devices.push(JoinedDevice {
+                kind,
+                unique_id: device["uniqueid"].as_str().map(str::to_string),
+                name: device["name"].as_str().map(str::to_string).unwrap_or(name),
+                id,
+            });
+        }
+        devices.sort_by(|a, b| (a.kind.path(), &a.id).cmp(&(b.kind.path(), &b.id)));
+        Ok(devices)
+    }
+}
+
+
+#[cfg(test)]
+mod device_search_tests {
+    use std::time::Duration;
+
+    use httpmock::prelude::*;
+    use serde_json::json;
+    use url::Url;
+
+    use crate::connection::DeconzConnection;
+    use crate::device_search::{DeviceKind, DeviceSearchOptions, JoinedDevice};
+
+    #[tokio::test]
+    async fn test_search_new_devices() {
+        let server = MockServer::start();
+        let permit_join = server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/config").json_body(json!({"permitjoin": 1}));
+            then.status(200).body(r#"[{"success": {"/config/permitjoin": 1}}]"#);
+        });
+        for path in ["lights", "sensors"] {
+            server.mock(|when, then| {
+                when.method(POST).path(format!("/api/KEY/{path}"));
+                then.status(200).json_body(json!([{"success": {format!("/{path}"): "Searching for new devices"}}]));
+            });
+        }
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/lights/new");
+            then.status(200).json_body(json!({"7": {"name": "Light 7"}, "lastscan": "active"}));
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/sensors/new");
+            then.status(200).json_body(json!({"lastscan": "active"}));
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/lights/7");
+            then.status(200).json_body(json!({"name": "Light 7", "uniqueid": "00:21:2e:ff:ff:00:73:9f-0a"}));
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let connection = DeconzConnection::new(url, "KEY".to_string()).unwrap();
+        let options = DeviceSearchOptions {
+            duration: Duration::from_secs(1),
+            poll_interval: Duration::from_millis(200),
+        };
+
+        let devices = connection.search_new_devices(options).await.unwrap();
+        assert_eq!(devices, vec![JoinedDevice {
+            kind: DeviceKind::Light,
+            id: "7".to_string(),
+            name: "Light 7".to_string(),
+            unique_id: Some("00:21:2e:ff:ff:00:73:9f-0a".to_string()),
+        }]);
+        permit_join.assert();
+    }
+}
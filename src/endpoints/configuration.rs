@@ -1,7 +1,15 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
 use anyhow::{anyhow, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::NaiveDateTime;
 
 use serde::{Deserialize, Serialize};
 
+use crate::connection::DeconzError;
+
 
 #[derive(Serialize, Deserialize)]
 pub struct TokenRequest {
@@ -22,16 +30,13 @@ pub struct TokenResponse {
 
 impl TokenRequest {
     pub fn new(devicetype: String, username: Option<String>) -> Result<TokenRequest> {
-        match &username {
-            Some(username) => {
-                if username.len() < 10 || username.len() > 40 {
-                    return Err(anyhow!(
-                        "invalid username length (is: {}, expected 10 <= len <= 40)",
-                        username.len(),
-                    ));
-                }
+        if let Some(username) = &username {
+            if username.len() < 10 || username.len() > 40 {
+                return Err(anyhow!(
+                    "invalid username length (is: {}, expected 10 <= len <= 40)",
+                    username.len(),
+                ));
             }
-            None => {}
         }
         if devicetype.len() > 40 {
             Err(anyhow!(
@@ -46,3 +51,318 @@ impl TokenRequest {
         }
     }
 }
+
+/// The gateway configuration as returned by `GET /api/<key>/config`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GatewayConfig {
+    #[serde(rename(serialize = "apiversion", deserialize = "apiversion"))]
+    pub api_version: String,
+    #[serde(rename(serialize = "bridgeid", deserialize = "bridgeid"))]
+    pub bridge_id: String,
+    #[serde(rename(serialize = "devicename", deserialize = "devicename"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dhcp: Option<bool>,
+    #[serde(rename(serialize = "fwversion", deserialize = "fwversion"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fw_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<Ipv4Addr>,
+    #[serde(rename(serialize = "ipaddress", deserialize = "ipaddress"))]
+    pub ip_address: Ipv4Addr,
+    #[serde(rename(serialize = "linkbutton", deserialize = "linkbutton"))]
+    pub link_button: bool,
+    #[serde(rename(serialize = "localtime", deserialize = "localtime"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_time: Option<NaiveDateTime>,
+    pub mac: String,
+    #[serde(rename(serialize = "modelid", deserialize = "modelid"))]
+    pub model_id: String,
+    pub name: String,
+    pub netmask: Ipv4Addr,
+    #[serde(rename(serialize = "networkopenduration", deserialize = "networkopenduration"))]
+    pub network_open_duration: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ntp: Option<String>,
+    #[serde(rename(serialize = "panid", deserialize = "panid"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pan_id: Option<u16>,
+    #[serde(rename(serialize = "portalservices", deserialize = "portalservices"))]
+    pub portal_services: bool,
+    #[serde(rename(serialize = "proxyaddress", deserialize = "proxyaddress"))]
+    #[serde(default)]
+    pub proxy_address: String,
+    #[serde(rename(serialize = "proxyport", deserialize = "proxyport"))]
+    #[serde(default)]
+    pub proxy_port: u16,
+    #[serde(rename(serialize = "rfconnected", deserialize = "rfconnected"))]
+    pub rf_connected: bool,
+    #[serde(rename(serialize = "swupdate", deserialize = "swupdate"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sw_update: Option<SoftwareUpdate>,
+    #[serde(rename(serialize = "swversion", deserialize = "swversion"))]
+    pub sw_version: String,
+    #[serde(rename(serialize = "timeformat", deserialize = "timeformat"))]
+    pub time_format: TimeFormat,
+    pub timezone: String,
+    #[serde(rename(serialize = "UTC", deserialize = "UTC"))]
+    pub utc: NaiveDateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    #[serde(rename(serialize = "websocketnotifyall", deserialize = "websocketnotifyall"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub websocket_notify_all: Option<bool>,
+    #[serde(rename(serialize = "websocketport", deserialize = "websocketport"))]
+    pub websocket_port: u16,
+    pub whitelist: HashMap<String, WhitelistEntry>,
+    #[serde(rename(serialize = "zigbeechannel", deserialize = "zigbeechannel"))]
+    pub zigbee_channel: u8,
+}
+
+/// The basic configuration returned by `GET /api/config` without an api key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GatewayInfo {
+    #[serde(rename(serialize = "apiversion", deserialize = "apiversion"))]
+    pub api_version: String,
+    #[serde(rename(serialize = "bridgeid", deserialize = "bridgeid"))]
+    pub bridge_id: String,
+    #[serde(rename(serialize = "datastoreversion", deserialize = "datastoreversion"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datastore_version: Option<String>,
+    #[serde(rename(serialize = "devicename", deserialize = "devicename"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    #[serde(rename(serialize = "factorynew", deserialize = "factorynew"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub factory_new: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    #[serde(rename(serialize = "modelid", deserialize = "modelid"))]
+    pub model_id: String,
+    pub name: String,
+    #[serde(rename(serialize = "swversion", deserialize = "swversion"))]
+    pub sw_version: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SoftwareUpdate {
+    pub notify: bool,
+    pub text: String,
+    #[serde(rename(serialize = "updatestate", deserialize = "updatestate"))]
+    pub update_state: UpdateState,
+    pub url: String,
+}
+
+/// The `swupdate.updatestate` of the gateway.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(from = "u8", into = "u8")]
+pub enum UpdateState {
+    NoUpdate,
+    Downloading,
+    ReadyToInstall,
+    Installing,
+    Unknown(u8),
+}
+
+impl From<u8> for UpdateState {
+    fn from(state: u8) -> Self {
+        match state {
+            0 => UpdateState::NoUpdate,
+            1 => UpdateState::Downloading,
+            2 => UpdateState::ReadyToInstall,
+            3 => UpdateState::Installing,
+            state => UpdateState::Unknown(state),
+        }
+    }
+}
+
+impl From<UpdateState> for u8 {
+    fn from(state: UpdateState) -> Self {
+        match state {
+            UpdateState::NoUpdate => 0,
+            UpdateState::Downloading => 1,
+            UpdateState::ReadyToInstall => 2,
+            UpdateState::Installing => 3,
+            UpdateState::Unknown(state) => state,
+        }
+    }
+}
+
+/// An api key known to the gateway.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WhitelistEntry {
+    #[serde(rename(serialize = "create date", deserialize = "create date"))]
+    pub create_date: NaiveDateTime,
+    #[serde(rename(serialize = "last use date", deserialize = "last use date"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_use_date: Option<NaiveDateTime>,
+    pub name: String,
+}
+
+impl WhitelistEntry {
+    /// When the key was last used, or created if it was never used.
+    pub fn last_active(&self) -> NaiveDateTime {
+        self.last_use_date.unwrap_or(self.create_date)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    #[serde(rename(serialize = "12h", deserialize = "12h"))]
+    TwelveHour,
+    #[serde(rename(serialize = "24h", deserialize = "24h"))]
+    TwentyFourHour,
+}
+
+/// A partial update of the gateway configuration, sent with `PUT /api/<key>/config`.
+///
+/// Only fields that are set are sent to the gateway.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ConfigUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename(serialize = "zigbeechannel", deserialize = "zigbeechannel"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zigbee_channel: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(rename(serialize = "timeformat", deserialize = "timeformat"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_format: Option<TimeFormat>,
+    /// Sets the gateway time, only useful when it is not synchronized via NTP.
+    #[serde(rename(serialize = "UTC", deserialize = "UTC"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utc: Option<NaiveDateTime>,
+    #[serde(rename(serialize = "networkopenduration", deserialize = "networkopenduration"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_open_duration: Option<u16>,
+    #[serde(rename(serialize = "permitjoin", deserialize = "permitjoin"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permit_join: Option<u8>,
+    #[serde(rename(serialize = "groupdelay", deserialize = "groupdelay"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_delay: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unlock: Option<u16>,
+    #[serde(rename(serialize = "rfconnected", deserialize = "rfconnected"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rf_connected: Option<bool>,
+    #[serde(rename(serialize = "otauactive", deserialize = "otauactive"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otau_active: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discovery: Option<bool>,
+    #[serde(rename(serialize = "lightlastseeninterval", deserialize = "lightlastseeninterval"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub light_last_seen_interval: Option<u16>,
+    #[serde(rename(serialize = "websocketnotifyall", deserialize = "websocketnotifyall"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub websocket_notify_all: Option<bool>,
+}
+
+pub const ZIGBEE_CHANNELS: [u8; 4] = [11, 15, 20, 25];
+
+impl ConfigUpdate {
+    /// Checks the values against the ranges documented by deCONZ.
+    pub fn validate(&self) -> Result<(), DeconzError> {
+        if let Some(name) = &self.name {
+            if name.chars().count() > 16 {
+                return Err(invalid("name", format!("must be at most 16 characters, is {}", name.chars().count())));
+            }
+        }
+        if let Some(channel) = self.zigbee_channel {
+            if !ZIGBEE_CHANNELS.contains(&channel) {
+                return Err(invalid("zigbeechannel", format!("must be one of {ZIGBEE_CHANNELS:?}, is {channel}")));
+            }
+        }
+        if let Some(timezone) = &self.timezone {
+            if timezone.is_empty() {
+                return Err(invalid("timezone", "must not be empty".to_string()));
+            }
+        }
+        if let Some(group_delay) = self.group_delay {
+            if group_delay > 5000 {
+                return Err(invalid("groupdelay", format!("must be at most 5000 ms, is {group_delay}")));
+            }
+        }
+        if let Some(unlock) = self.unlock {
+            if unlock > 600 {
+                return Err(invalid("unlock", format!("must be at most 600 seconds, is {unlock}")));
+            }
+        }
+        if self.light_last_seen_interval == Some(0) {
+            return Err(invalid("lightlastseeninterval", "must be at least 1 second".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn invalid(parameter: &'static str, reason: String) -> DeconzError {
+    DeconzError::InvalidParameter { parameter, reason }
+}
+
+/// Request body of `PUT /api/<key>/config/password`.
+///
+/// deCONZ expects both passwords as Base64 encoded `username:password` pairs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PasswordChange {
+    pub username: String,
+    #[serde(rename(serialize = "oldhash", deserialize = "oldhash"))]
+    pub old_hash: String,
+    #[serde(rename(serialize = "newhash", deserialize = "newhash"))]
+    pub new_hash: String,
+}
+
+impl PasswordChange {
+    pub fn new(username: &str, old_password: &str, new_password: &str) -> PasswordChange {
+        PasswordChange {
+            username: username.to_string(),
+            old_hash: password_hash(username, old_password),
+            new_hash: password_hash(username, new_password),
+        }
+    }
+}
+
+/// Encodes credentials the way deCONZ expects them, e.g. `delight:delight` -> `ZGVsaWdodDpkZWxpZ2h0`.
+pub fn password_hash(username: &str, password: &str) -> String {
+    BASE64.encode(format!("{username}:{password}"))
+}
+
+/// Request body of `POST /api/<key>/config/reset`, choosing what the factory reset wipes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FactoryReset {
+    /// Resets the Zigbee network settings to factory defaults.
+    #[serde(rename(serialize = "resetGW", deserialize = "resetGW"))]
+    pub reset_network: bool,
+    /// Deletes the database with all lights, groups, scenes, sensors, rules and schedules.
+    #[serde(rename(serialize = "deleteDB", deserialize = "deleteDB"))]
+    pub delete_database: bool,
+}
+
+/// Confirms a factory reset of one specific gateway.
+///
+/// The reset is only sent when the bridge id matches the gateway the connection talks to, so a
+/// connection pointed at the wrong host can't wipe it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResetConfirmation {
+    bridge_id: String,
+}
+
+impl ResetConfirmation {
+    pub fn for_bridge(bridge_id: impl Into<String>) -> ResetConfirmation {
+        ResetConfirmation {
+            bridge_id: bridge_id.into(),
+        }
+    }
+
+    pub fn bridge_id(&self) -> &str {
+        &self.bridge_id
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FactoryResetResult {
+    pub bridge_id: String,
+    pub reset: FactoryReset,
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+
+use crate::endpoints::configuration::GatewayConfig;
+use crate::endpoints::groups::GetGroupsEntry;
+use crate::endpoints::light::Light;
+
+/// Everything the gateway knows, as returned by `GET /api/<key>`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FullState {
+    /// Kept raw if it doesn't match [`GatewayConfig`], e.g. on firmware that leaves out fields.
+    pub config: MaybeParsed<GatewayConfig>,
+    #[serde(default)]
+    pub lights: HashMap<String, MaybeParsed<Light>>,
+    #[serde(default)]
+    pub groups: HashMap<String, MaybeParsed<GetGroupsEntry>>,
+    #[serde(default)]
+    pub sensors: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub scenes: HashMap<String, serde_json::Value>,
+    /// Kept as JSON like sensors and scenes, the rule and schedule types don't match responses yet.
+    #[serde(default)]
+    pub schedules: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub rules: HashMap<String, serde_json::Value>,
+}
+
+/// A resource that is kept as raw JSON if it doesn't match the expected type, so one unusual
+/// device doesn't fail the whole snapshot.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum MaybeParsed<T> {
+    Parsed(T),
+    Raw(serde_json::Value),
+}
+
+impl<T> MaybeParsed<T> {
+    pub fn parsed(&self) -> Option<&T> {
+        match self {
+            MaybeParsed::Parsed(parsed) => Some(parsed),
+            MaybeParsed::Raw(_) => None,
+        }
+    }
+
+    pub fn is_raw(&self) -> bool {
+        matches!(self, MaybeParsed::Raw(_))
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for MaybeParsed<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        match T::deserialize(&value) {
+            Ok(parsed) => Ok(MaybeParsed::Parsed(parsed)),
+            Err(_) => Ok(MaybeParsed::Raw(value)),
+        }
+    }
+}
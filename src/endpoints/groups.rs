@@ -12,12 +12,12 @@ pub struct CreateGroupResponse {
     pub name: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct CreateGroupResponseWrapper {
     pub success: CreateGroupResponse,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct GetAllGroupsEntry {
     #[serde(rename(serialize = "devicemembership", deserialize = "devicemembership"))]
     pub device_membership: Option<Vec<String>>,
@@ -26,7 +26,7 @@ pub struct GetAllGroupsEntry {
     pub hidden: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct GetGroupsEntry {
     #[serde(rename(serialize = "devicemembership", deserialize = "devicemembership"))]
     pub device_membership: Option<Vec<String>>,
@@ -41,13 +41,13 @@ pub struct GetGroupsEntry {
     pub scenes: Vec<SceneEntry>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct SceneEntry {
     id: String,
     name: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct GroupAction {
     pub on: bool,
     pub bri: u8,
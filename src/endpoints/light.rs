@@ -1,8 +1,25 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::color::{kelvin_to_mired, kelvin_to_xy, mired_to_kelvin, Gamut, Hsv, Rgb};
+
+/// Bits of [`Light::color_capabilities`].
+pub const CAPABILITY_HUE_SATURATION: u64 = 0x01;
+pub const CAPABILITY_ENHANCED_HUE: u64 = 0x02;
+pub const CAPABILITY_COLOR_LOOP: u64 = 0x04;
+pub const CAPABILITY_XY: u64 = 0x08;
+pub const CAPABILITY_COLOR_TEMPERATURE: u64 = 0x10;
+
+/// The `ct` range of the api, used for lights that don't report `ctmin` and `ctmax`.
+pub const MIN_MIRED: u64 = 153;
+pub const MAX_MIRED: u64 = 500;
+pub const MAX_HUE: u32 = 65535;
+pub const MAX_TRANSITION_TIME: u64 = 65535;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[derive(PartialEq)]
 pub struct Light {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<LightCapabilities>,
     #[serde(rename(serialize = "colorcapabilities", deserialize = "colorcapabilities"))]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub color_capabilities: Option<u64>,
@@ -21,7 +38,7 @@ pub struct Light {
     pub etag: String,
     #[serde(rename(serialize = "hascolor", deserialize = "hascolor"))]
     pub has_color: bool,
-    #[serde(rename(serialize = "manufacturername", deserialize = "manufacturername"))]
+    #[serde(rename(serialize = "manufacturername", deserialize = "manufacturername"), alias = "manufacturer")]
     pub manufacturer_name: String,
     pub name: String,
     #[serde(rename(serialize = "modelid", deserialize = "modelid"))]
@@ -32,11 +49,115 @@ pub struct Light {
     #[serde(rename(serialize = "swversion", deserialize = "swversion"))]
     pub sw_version: String,
     pub r#type: String,
+    #[serde(deserialize_with = "deserialize_current_state")]
     pub state: LightState,
     #[serde(rename(serialize = "uniqueid", deserialize = "uniqueid"))]
     pub unique_id: String,
 }
 
+/// Reads a reported light state, keeping only the color of its `colormode`.
+///
+/// The gateway also reports the last values of the other color modes. Keeping them would make the
+/// state ask for several colors at once when it is sent back.
+fn deserialize_current_state<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LightState, D::Error> {
+    #[derive(Deserialize)]
+    struct ReportedState {
+        #[serde(flatten)]
+        state: LightState,
+        colormode: Option<ColorMode>,
+    }
+
+    let ReportedState { mut state, colormode } = ReportedState::deserialize(deserializer)?;
+    let current = match colormode {
+        Some(ColorMode::Ct) => LightState { ct: state.ct, ..Default::default() },
+        Some(ColorMode::Xy) => LightState { xy: state.xy, ..Default::default() },
+        Some(ColorMode::Hs) => LightState { hue: state.hue, sat: state.sat, ..Default::default() },
+        Some(ColorMode::None) | None => return Ok(state),
+    };
+    state.merge(&current);
+    Ok(state)
+}
+
+/// Detailed capabilities, reported by newer gateway versions.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[derive(PartialEq)]
+pub struct LightCapabilities {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorCapabilities>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[derive(PartialEq)]
+pub struct ColorCapabilities {
+    /// The gamut of the light.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xy: Option<Gamut>,
+}
+
+/// The mutable attributes of a light, set with `PUT /api/<key>/lights/<id>`.
+///
+/// The gateway only lets you rename a light there, its other settings are in [`LightConfig`].
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[derive(PartialEq)]
+pub struct LightAttributes {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Settings stored in a light, set with `PUT /api/<key>/lights/<id>/config`.
+///
+/// Only fields that are set are sent to the gateway. Lights ignore settings they don't support.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[derive(PartialEq)]
+pub struct LightConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bri: Option<BrightnessConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on: Option<OnConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[derive(PartialEq)]
+pub struct BrightnessConfig {
+    /// Lets the color temperature follow the brightness, like an incandescent bulb.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub couple_ct: Option<bool>,
+    /// Applies brightness changes while the light is off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub execute_if_off: Option<bool>,
+    /// Transition time of turning the light on and off, in 1/10 seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_off_transition_time: Option<u16>,
+    /// Brightness after power loss.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[derive(PartialEq)]
+pub struct ColorConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ct: Option<StartupConfig<u16>>,
+    /// Applies color changes while the light is off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub execute_if_off: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xy: Option<StartupConfig<[f64; 2]>>,
+}
+
+/// The value a light starts with after power loss.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[derive(PartialEq)]
+pub struct StartupConfig<T> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup: Option<T>,
+}
+
+/// Whether a light is on after power loss.
+pub type OnConfig = StartupConfig<bool>;
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
 #[derive(PartialEq)]
 pub struct LightState {
@@ -91,6 +212,119 @@ pub enum LightEffect {
     ColorLoop,
 }
 
+/// A light state the gateway would reject, found by [`LightState::validate`].
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum LightStateError {
+    #[error("{field} is {value}, but must be between {min} and {max}")]
+    OutOfRange {
+        field: &'static str,
+        value: f64,
+        min: f64,
+        max: f64,
+    },
+    #[error("The light doesn't support {field}")]
+    Unsupported {
+        field: &'static str,
+    },
+    #[error("Only one color mode can be set at once, got {modes:?}")]
+    ConflictingColorModes {
+        modes: Vec<&'static str>,
+    },
+}
+
+impl LightState {
+    /// Checks ranges and color modes, then that `light` supports every color field that is set.
+    /// Color temperatures must lie in the light's `ctmin` to `ctmax` range.
+    pub fn validate(&self, light: &Light) -> Result<(), LightStateError> {
+        self.check_ranges(light.mired_range())?;
+        let unsupported = [
+            ("ct", self.ct.is_some() && !light.supports_color_temperature()),
+            ("xy", self.xy.is_some() && !light.supports_xy()),
+            ("hue", self.hue.is_some() && !light.supports_hue_saturation()),
+            ("sat", self.sat.is_some() && !light.supports_hue_saturation()),
+        ];
+        match unsupported.iter().find(|(_, unsupported)| *unsupported) {
+            Some((field, _)) => Err(LightStateError::Unsupported { field }),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks ranges and color modes without knowing the light, color temperatures must lie
+    /// between [`MIN_MIRED`] and [`MAX_MIRED`].
+    pub fn validate_ranges(&self) -> Result<(), LightStateError> {
+        self.check_ranges((MIN_MIRED, MAX_MIRED))
+    }
+
+    fn check_ranges(&self, (min_mired, max_mired): (u64, u64)) -> Result<(), LightStateError> {
+        let check = |field, value: Option<f64>, min: f64, max: f64| match value {
+            Some(value) if !(min..=max).contains(&value) => Err(LightStateError::OutOfRange { field, value, min, max }),
+            _ => Ok(()),
+        };
+        check("hue", self.hue.map(f64::from), 0.0, MAX_HUE as f64)?;
+        check("ct", self.ct.map(|ct| ct as f64), min_mired as f64, max_mired as f64)?;
+        check("transitiontime", self.transition_time.map(|time| time as f64), 0.0, MAX_TRANSITION_TIME as f64)?;
+        if let Some([x, y]) = self.xy {
+            check("xy", Some(x), 0.0, 1.0)?;
+            check("xy", Some(y), 0.0, 1.0)?;
+        }
+
+        let modes: Vec<&'static str> = [
+            ("xy", self.xy.is_some()),
+            ("ct", self.ct.is_some()),
+            ("hs", self.hue.is_some() || self.sat.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(mode, set)| set.then_some(mode))
+        .collect();
+        if modes.len() > 1 {
+            return Err(LightStateError::ConflictingColorModes { modes });
+        }
+        Ok(())
+    }
+
+    /// Sets `ct` without clamping, use [`Light::set_color_temperature_kelvin`] to stay in the
+    /// light's range.
+    pub fn set_color_temperature_kelvin(&mut self, kelvin: u32) -> &mut Self {
+        self.merge(&LightState { ct: Some(kelvin_to_mired(kelvin)), ..Default::default() });
+        self
+    }
+
+    pub fn color_temperature_kelvin(&self) -> Option<u32> {
+        self.ct.map(mired_to_kelvin)
+    }
+
+    /// Merges a newer state into this one, fields set in `newer` win.
+    ///
+    /// Setting a color in one mode (xy, ct or hue/sat) clears older values of the other modes, so
+    /// the merged state never asks for two different colors at once.
+    pub fn merge(&mut self, newer: &LightState) {
+        if newer.xy.is_some() {
+            self.ct = None;
+            self.hue = None;
+            self.sat = None;
+        }
+        if newer.ct.is_some() {
+            self.xy = None;
+            self.hue = None;
+            self.sat = None;
+        }
+        if newer.hue.is_some() || newer.sat.is_some() {
+            self.xy = None;
+            self.ct = None;
+        }
+
+        self.alert = newer.alert.or(self.alert);
+        self.bri = newer.bri.or(self.bri);
+        self.effect = newer.effect.or(self.effect);
+        self.color_loop_speed = newer.color_loop_speed.or(self.color_loop_speed);
+        self.ct = newer.ct.or(self.ct);
+        self.hue = newer.hue.or(self.hue);
+        self.on = newer.on.or(self.on);
+        self.sat = newer.sat.or(self.sat);
+        self.transition_time = newer.transition_time.or(self.transition_time);
+        self.xy = newer.xy.or(self.xy);
+    }
+}
 
 impl Light {
     pub fn change_brightness(&mut self, delta: i16) -> &mut Self {
@@ -106,13 +340,96 @@ impl Light {
     }
 
     pub fn change_color_temperature(&mut self, delta: i128) -> &mut Self {
-        if self.ct_max.is_none() || self.ct_min.is_some() { return self; }
-        let new_ct = self.state.ct.unwrap_or(0) as i128 + delta;
-        self.state.ct = Some(new_ct.max(self.ct_min.unwrap().into()).min(self.ct_max.unwrap().into()) as u64);
+        if !self.supports_color_temperature() {
+            return self;
+        }
+        let (min, max) = self.mired_range();
+        let current = self.state.ct.unwrap_or(min) as i128;
+        let ct = (current + delta).clamp(min as i128, max as i128) as u64;
+        self.state.merge(&LightState { ct: Some(ct), ..Default::default() });
         self
     }
     pub fn on(&mut self, on: bool) -> &mut Self {
         self.state.on = Some(on);
         self
     }
+
+    /// Color lights that don't report `colorcapabilities` are assumed to support xy.
+    pub fn supports_xy(&self) -> bool {
+        self.has_color && self.color_capabilities.is_none_or(|bits| bits & CAPABILITY_XY != 0)
+    }
+
+    pub fn supports_hue_saturation(&self) -> bool {
+        self.has_color && self.color_capabilities.is_none_or(|bits| bits & CAPABILITY_HUE_SATURATION != 0)
+    }
+
+    /// Lights that don't report `colorcapabilities` support color temperature if they report
+    /// a `ct` range or value.
+    pub fn supports_color_temperature(&self) -> bool {
+        match self.color_capabilities {
+            Some(bits) => bits & CAPABILITY_COLOR_TEMPERATURE != 0,
+            None => self.ct_min.is_some() || self.ct_max.is_some() || self.state.ct.is_some(),
+        }
+    }
+
+    /// The `ct` range of the light in mired, [`MIN_MIRED`] to [`MAX_MIRED`] if it isn't reported.
+    pub fn mired_range(&self) -> (u64, u64) {
+        (self.ct_min.unwrap_or(MIN_MIRED), self.ct_max.unwrap_or(MAX_MIRED))
+    }
+
+    /// Sets the color temperature clamped to the light's range. Color lights without color
+    /// temperature get the matching xy color instead.
+    pub fn set_color_temperature_kelvin(&mut self, kelvin: u32) -> &mut Self {
+        if self.supports_color_temperature() {
+            let (min, max) = self.mired_range();
+            let ct = kelvin_to_mired(kelvin).clamp(min, max);
+            self.state.merge(&LightState { ct: Some(ct), ..Default::default() });
+        } else if self.supports_xy() {
+            let xy = self.gamut().clamp(kelvin_to_xy(kelvin));
+            self.state.merge(&LightState { xy: Some(xy.into()), ..Default::default() });
+        }
+        self
+    }
+
+    /// The reported gamut of the light, or [`Gamut::C`].
+    pub fn gamut(&self) -> Gamut {
+        self.capabilities
+            .as_ref()
+            .and_then(|capabilities| capabilities.color.as_ref())
+            .and_then(|color| color.xy)
+            .unwrap_or_default()
+    }
+
+    /// Sets the color as xy clamped to the light's gamut, or as hue and saturation if the light
+    /// doesn't support xy. Lights without color only get the brightness.
+    pub fn set_color(&mut self, color: Rgb) -> &mut Self {
+        let hsv = Hsv::from(color);
+        let mut state = LightState { bri: Some(hsv.brightness()), ..Default::default() };
+        if self.supports_xy() {
+            let (xy, bri) = color.to_xy();
+            state.xy = Some(self.gamut().clamp(xy).into());
+            state.bri = Some(bri);
+        } else if self.supports_hue_saturation() {
+            let (hue, sat) = hsv.to_hue_sat();
+            state.hue = Some(hue);
+            state.sat = Some(sat);
+        }
+        self.state.merge(&state);
+        self
+    }
+
+    /// Like [`Self::set_color`], but prefers hue and saturation if the light supports them.
+    pub fn set_hsv(&mut self, hsv: Hsv) -> &mut Self {
+        if !self.supports_hue_saturation() {
+            return self.set_color(hsv.into());
+        }
+        let (hue, sat) = hsv.to_hue_sat();
+        self.state.merge(&LightState {
+            bri: Some(hsv.brightness()),
+            hue: Some(hue),
+            sat: Some(sat),
+            ..Default::default()
+        });
+        self
+    }
 }
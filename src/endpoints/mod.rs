@@ -1,4 +1,5 @@
 pub mod configuration;
+pub mod full_state;
 pub mod light;
 pub mod groups;
 pub mod rules;
@@ -1,2 +1,13 @@
+pub mod backup;
+mod coalesce;
+pub mod color;
 pub mod connection;
+pub mod device_search;
 pub mod endpoints;
+pub mod pairing;
+pub mod rate_limit;
+pub mod retry;
+pub mod software_update;
+pub mod version;
+#[cfg(test)]
+mod test_server;
@@ -0,0 +1,219 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::connection::{request_token, DeconzConnection, DeconzConnectionBuilder, DeconzError, DeconzErrorType};
+use crate::endpoints::configuration::TokenRequest;
+
+/// How long and how often [`DeconzConnectionBuilder::pair`] asks the gateway for an api key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairingOptions {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for PairingOptions {
+    fn default() -> Self {
+        PairingOptions {
+            interval: Duration::from_secs(2),
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PairingProgress {
+    /// The gateway is still locked, the next attempt follows after the interval.
+    WaitingForLinkButton {
+        attempt: u32,
+        remaining: Duration,
+    },
+    Paired {
+        attempt: u32,
+    },
+}
+
+impl DeconzConnectionBuilder {
+    /// Requests an api key until the gateway is unlocked or `options.timeout` has passed.
+    ///
+    /// The user unlocks the gateway with "Authenticate app" in the Phoscon gateway settings.
+    /// Errors other than [`DeconzErrorType::LinkButtonNotPressed`] end pairing immediately. A
+    /// request that is still pending when the timeout passes is cancelled.
+    ///
+    /// ```no_run
+    /// # use deconz_rs::connection::DeconzConnection;
+    /// # use deconz_rs::endpoints::configuration::TokenRequest;
+    /// # use deconz_rs::pairing::{PairingOptions, PairingProgress};
+    /// # async fn example() -> Result<(), deconz_rs::connection::DeconzError> {
+    /// let request = TokenRequest::new("my-app".to_string(), None).unwrap();
+    /// let connection = DeconzConnection::builder("http://192.168.0.166".parse().unwrap())
+    ///     .pair(request, PairingOptions::default(), |progress| {
+    ///         if let PairingProgress::WaitingForLinkButton { .. } = progress {
+    ///             println!("Press \"Authenticate app\" in Phoscon");
+    ///         }
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn pair<Progress>(
+        mut self,
+        requested_user: TokenRequest,
+        options: PairingOptions,
+        mut on_progress: Progress,
+    ) -> Result<DeconzConnection, DeconzError>
+        where
+            Progress: FnMut(PairingProgress),
+    {
+        let url = self.base_url()?;
+        let client = self.build_client()?;
+        let deadline = Instant::now() + options.timeout;
+
+        let mut attempt = 1;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let response = tokio::time::timeout(remaining, request_token(&client, &url, &requested_user))
+                .await
+                .map_err(|_| DeconzError::PairingTimeout { attempts: attempt })?;
+            match response {
+                Ok(api_key) => {
+                    on_progress(PairingProgress::Paired { attempt });
+                    return self.finish(url, api_key, client);
+                }
+                Err(err) if err.error_type() == Some(DeconzErrorType::LinkButtonNotPressed) => {}
+                Err(err) => return Err(err),
+            }
+
+            let now = Instant::now();
+            if now + options.interval > deadline {
+                return Err(DeconzError::PairingTimeout { attempts: attempt });
+            }
+            on_progress(PairingProgress::WaitingForLinkButton {
+                attempt,
+                remaining: deadline - now,
+            });
+            tokio::time::sleep(options.interval).await;
+            attempt += 1;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod pairing_tests {
+    use std::time::Duration;
+
+    use httpmock::prelude::*;
+    use url::Url;
+
+    use crate::connection::{DeconzConnection, DeconzError};
+    use crate::endpoints::configuration::TokenRequest;
+    use crate::pairing::{PairingOptions, PairingProgress};
+    use crate::test_server::{sequence_server, Reply};
+
+    #[tokio::test]
+    async fn test_pairing_times_out() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST).path("/api");
+            then.status(403).body(r#"[{"error": {"type": 101, "address": "/", "description": "link button not pressed"}}]"#);
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let request = TokenRequest::new("deconz-rs".to_string(), None).unwrap();
+        // Leaves plenty of time for a second attempt, even if requests are slow.
+        let options = PairingOptions {
+            interval: Duration::from_millis(10),
+            timeout: Duration::from_millis(500),
+        };
+        let mut progress = vec![];
+
+        let result = DeconzConnection::builder(url)
+            .pair(request, options, |update| progress.push(update))
+            .await;
+        match result {
+            Err(DeconzError::PairingTimeout { attempts }) => {
+                assert!(attempts > 1);
+                assert_eq!(progress.len(), attempts as usize - 1);
+                // The last attempt may have been cancelled before it reached the gateway.
+                assert!((attempts as usize - 1..=attempts as usize).contains(&mock.hits()));
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pairing_times_out_while_gateway_hangs() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move {
+            let mut connections = vec![];
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                connections.push(stream);
+            }
+        });
+        let request = TokenRequest::new("deconz-rs".to_string(), None).unwrap();
+        let options = PairingOptions {
+            interval: Duration::from_millis(10),
+            timeout: Duration::from_millis(100),
+        };
+        let start = tokio::time::Instant::now();
+
+        let result = DeconzConnection::builder(url).pair(request, options, |_| {}).await;
+        assert!(matches!(result, Err(DeconzError::PairingTimeout { attempts: 1 })));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_pairing_waits_for_link_button() {
+        let locked = || {
+            Reply::Json(403, r#"[{"error": {"type": 101, "address": "/", "description": "link button not pressed"}}]"#.to_string())
+        };
+        let (url, server) = sequence_server(vec![
+            locked(),
+            locked(),
+            Reply::Json(200, r#"[{"success": {"username": "83B7780291"}}]"#.to_string()),
+        ])
+        .await;
+        let request = TokenRequest::new("deconz-rs".to_string(), None).unwrap();
+        let options = PairingOptions {
+            interval: Duration::from_millis(1),
+            timeout: Duration::from_secs(10),
+        };
+        let mut progress = vec![];
+
+        let connection = DeconzConnection::builder(url)
+            .pair(request, options, |update| progress.push(update))
+            .await
+            .unwrap();
+        assert_eq!(connection.get_api_key(), "83B7780291");
+        assert!(matches!(
+            progress[..],
+            [
+                PairingProgress::WaitingForLinkButton { attempt: 1, .. },
+                PairingProgress::WaitingForLinkButton { attempt: 2, .. },
+                PairingProgress::Paired { attempt: 3 },
+            ]
+        ));
+        assert_eq!(server.await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_pairing_succeeds() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST).path("/api");
+            then.status(200).body(r#"[{"success": {"username": "83B7780291"}}]"#);
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let request = TokenRequest::new("deconz-rs".to_string(), None).unwrap();
+        let mut progress = vec![];
+
+        let connection = DeconzConnection::builder(url)
+            .pair(request, PairingOptions::default(), |update| progress.push(update))
+            .await
+            .unwrap();
+        assert_eq!(connection.get_api_key(), "83B7780291");
+        assert_eq!(progress, vec![PairingProgress::Paired { attempt: 1 }]);
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::connection::DeconzError;
+
+/// A light or group that commands are sent to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Resource {
+    Light(String),
+    Group(String),
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Light(id) => write!(f, "/lights/{id}"),
+            Resource::Group(id) => write!(f, "/groups/{id}"),
+        }
+    }
+}
+
+/// What happens to commands that arrive faster than the rate limit allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Callers wait until their command can be sent.
+    Wait,
+    /// At most this many commands wait per resource, older ones fail with [`DeconzError::Dropped`].
+    DropOldest(usize),
+}
+
+/// Limits how many commands per second are sent to each light and group.
+///
+/// Group commands are broadcast to the whole Zigbee network, so they get a separate, lower limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    pub light_commands_per_second: f64,
+    pub group_commands_per_second: f64,
+    pub overflow: OverflowPolicy,
+}
+
+/// The longest interval between two commands. Rates so low that their interval would be longer
+/// effectively stop sending.
+const MAX_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24 * 365);
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            light_commands_per_second: 5.0,
+            group_commands_per_second: 1.0,
+            overflow: OverflowPolicy::Wait,
+        }
+    }
+}
+
+impl RateLimit {
+    pub fn new(light_commands_per_second: f64, group_commands_per_second: f64) -> RateLimit {
+        RateLimit {
+            light_commands_per_second,
+            group_commands_per_second,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    fn interval(&self, resource: &Resource) -> Duration {
+        let per_second = match resource {
+            Resource::Light(_) => self.light_commands_per_second,
+            Resource::Group(_) => self.group_commands_per_second,
+        };
+        if per_second > 0.0 {
+            Duration::try_from_secs_f64(1.0 / per_second).map_or(MAX_INTERVAL, |interval| interval.min(MAX_INTERVAL))
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Queue {
+    next_slot: Instant,
+    next_ticket: u64,
+    waiting: VecDeque<u64>,
+}
+
+/// Hands out send slots per resource in FIFO order.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    queues: Mutex<HashMap<Resource, Queue>>,
+    changed: Notify,
+}
+
+/// Removes a waiting ticket from its queue when the caller gives up before being served.
+struct Ticket<'a> {
+    limiter: &'a RateLimiter,
+    resource: &'a Resource,
+    ticket: u64,
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        let mut queues = self.limiter.queues.lock().unwrap();
+        if let Some(queue) = queues.get_mut(self.resource) {
+            queue.waiting.retain(|ticket| *ticket != self.ticket);
+        }
+        drop(queues);
+        self.limiter.changed.notify_waiters();
+    }
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> RateLimiter {
+        RateLimiter {
+            limit,
+            queues: Mutex::new(HashMap::new()),
+            changed: Notify::new(),
+        }
+    }
+
+    /// Waits until a command may be sent to `resource`.
+    pub(crate) async fn acquire(&self, resource: &Resource) -> Result<(), DeconzError> {
+        let ticket = {
+            let mut queues = self.queues.lock().unwrap();
+            let queue = queues.entry(resource.clone()).or_insert_with(|| Queue {
+                next_slot: Instant::now(),
+                next_ticket: 0,
+                waiting: VecDeque::new(),
+            });
+            let ticket = queue.next_ticket;
+            queue.next_ticket += 1;
+            queue.waiting.push_back(ticket);
+            if let OverflowPolicy::DropOldest(capacity) = self.limit.overflow {
+                while queue.waiting.len() > capacity.max(1) {
+                    queue.waiting.pop_front();
+                }
+            }
+            Ticket {
+                limiter: self,
+                resource,
+                ticket,
+            }
+        };
+        self.changed.notify_waiters();
+
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            let deadline = {
+                let mut queues = self.queues.lock().unwrap();
+                let queue = queues.get_mut(resource).expect("queue exists while tickets are waiting");
+                if !queue.waiting.contains(&ticket.ticket) {
+                    return Err(DeconzError::Dropped(resource.to_string()));
+                }
+                if queue.waiting.front() == Some(&ticket.ticket) {
+                    let now = Instant::now();
+                    if now >= queue.next_slot {
+                        queue.waiting.pop_front();
+                        queue.next_slot = now + self.limit.interval(resource);
+                        return Ok(());
+                    }
+                    Some(queue.next_slot)
+                } else {
+                    None
+                }
+            };
+
+            match deadline {
+                Some(deadline) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(deadline) => {}
+                        _ = &mut changed => {}
+                    }
+                }
+                None => changed.await,
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use crate::connection::DeconzError;
+    use crate::rate_limit::{OverflowPolicy, RateLimit, RateLimiter, Resource};
+
+    #[tokio::test]
+    async fn test_commands_are_spaced() {
+        let limiter = RateLimiter::new(RateLimit::new(20.0, 1.0));
+        let light = Resource::Light("1".to_string());
+        let start = Instant::now();
+
+        let (a, b, c) = tokio::join!(limiter.acquire(&light), limiter.acquire(&light), limiter.acquire(&light));
+        assert!(a.is_ok() && b.is_ok() && c.is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_resources_are_independent() {
+        let limiter = RateLimiter::new(RateLimit::new(1.0, 1.0));
+        let start = Instant::now();
+
+        limiter.acquire(&Resource::Light("1".to_string())).await.unwrap();
+        limiter.acquire(&Resource::Light("2".to_string())).await.unwrap();
+        limiter.acquire(&Resource::Group("1".to_string())).await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_tiny_rates_dont_overflow() {
+        let limit = RateLimit::new(1e-300, f64::MIN_POSITIVE);
+        assert_eq!(limit.interval(&Resource::Light("1".to_string())), Duration::from_secs(60 * 60 * 24 * 365));
+        assert_eq!(limit.interval(&Resource::Group("1".to_string())), Duration::from_secs(60 * 60 * 24 * 365));
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest() {
+        let limit = RateLimit::new(20.0, 1.0).with_overflow(OverflowPolicy::DropOldest(1));
+        let limiter = RateLimiter::new(limit);
+        let light = Resource::Light("1".to_string());
+
+        let (a, b, c) = tokio::join!(limiter.acquire(&light), limiter.acquire(&light), limiter.acquire(&light));
+        assert!(a.is_ok());
+        assert!(matches!(b, Err(DeconzError::Dropped(_))));
+        assert!(c.is_ok());
+    }
+}
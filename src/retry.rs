@@ -0,0 +1,165 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::{Method, StatusCode};
+
+use crate::connection::{DeconzError, DeconzErrorType};
+
+/// Controls if and how failed requests are retried by a [`crate::connection::DeconzConnection`].
+///
+/// Requests are retried when the gateway reports [`DeconzErrorType::BridgeBusy`] or
+/// [`DeconzErrorType::NotConnected`], or when the request failed with a transient network error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Fraction of the backoff that is randomized, `0.0` disables jitter.
+    pub jitter: f64,
+    /// Also retry POST and DELETE requests, which are not idempotent.
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            multiplier: 2.0,
+            jitter: 0.2,
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_backoff(mut self, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_retry_non_idempotent(mut self, retry_non_idempotent: bool) -> Self {
+        self.retry_non_idempotent = retry_non_idempotent;
+        self
+    }
+
+    /// The delay before the attempt following `attempt` (starting at 1).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        if self.jitter <= 0.0 {
+            return Duration::from_secs_f64(backoff);
+        }
+        let factor = rand::thread_rng().gen_range((1.0 - self.jitter)..=1.0);
+        Duration::from_secs_f64(backoff * factor)
+    }
+
+    pub(crate) fn should_retry(&self, method: &Method, err: &DeconzError) -> bool {
+        let idempotent = matches!(*method, Method::GET | Method::PUT | Method::HEAD);
+        (idempotent || self.retry_non_idempotent) && is_transient(err)
+    }
+}
+
+/// How many requests a connection sent and how many attempts they took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryStats {
+    pub requests: u64,
+    /// All attempts, including the first attempt of every request.
+    pub attempts: u64,
+    /// Requests that needed more than one attempt, whether they succeeded or not.
+    pub retried_requests: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RetryCounters {
+    requests: AtomicU64,
+    attempts: AtomicU64,
+    retried_requests: AtomicU64,
+}
+
+impl RetryCounters {
+    pub(crate) fn record(&self, attempts: u32) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.attempts.fetch_add(attempts as u64, Ordering::Relaxed);
+        if attempts > 1 {
+            self.retried_requests.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> RetryStats {
+        RetryStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            attempts: self.attempts.load(Ordering::Relaxed),
+            retried_requests: self.retried_requests.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn is_transient(err: &DeconzError) -> bool {
+    match err {
+        DeconzError::Gateway(_) => matches!(
+            err.error_type(),
+            Some(DeconzErrorType::BridgeBusy) | Some(DeconzErrorType::NotConnected)
+        ),
+        DeconzError::Network(err) => err.is_timeout() || err.is_connect() || err.is_request(),
+        DeconzError::Status { status, .. } => matches!(
+            *status,
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        ),
+        _ => false,
+    }
+}
+
+
+#[cfg(test)]
+mod retry_tests {
+    use std::time::Duration;
+
+    use crate::retry::RetryPolicy;
+
+    #[test]
+    fn test_backoff_is_exponential_and_capped() {
+        let policy = RetryPolicy::new(5)
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(350))
+            .with_jitter(0.0);
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn test_backoff_jitter_stays_in_range() {
+        let policy = RetryPolicy::new(5)
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .with_jitter(0.5);
+
+        for _ in 0..100 {
+            let backoff = policy.backoff(1);
+            assert!(backoff >= Duration::from_millis(50) && backoff <= Duration::from_millis(100));
+        }
+    }
+}
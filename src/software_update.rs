@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::time::Instant;
+
+use crate::connection::{DeconzConnection, DeconzError};
+use crate::endpoints::configuration::{GatewayConfig, SoftwareUpdate, UpdateState};
+
+/// How often and how long [`DeconzConnection::wait_for_software_update`] polls the gateway.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdatePollOptions {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for UpdatePollOptions {
+    fn default() -> Self {
+        UpdatePollOptions {
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+impl DeconzConnection {
+    /// Returns the `swupdate` state of the gateway, if it reports one.
+    pub async fn check_software_update(&self) -> Result<Option<SoftwareUpdate>, DeconzError> {
+        Ok(self.get_config().await?.sw_update)
+    }
+
+    /// Starts the software update and returns the version that is being installed.
+    pub async fn trigger_software_update(&self) -> Result<String, DeconzError> {
+        let response: serde_json::Value = self.post("config/update", &json!({})).await?;
+        let success = match &response {
+            serde_json::Value::Array(entries) => entries.first().and_then(|entry| entry.get("success")),
+            entry => entry.get("success"),
+        };
+        match success.and_then(|success| success.get("/config/update")).and_then(|version| version.as_str()) {
+            Some(version) => Ok(version.to_string()),
+            None => {
+                let err = serde::de::Error::custom("missing /config/update in response");
+                Err(DeconzError::decode(err, &response.to_string()))
+            }
+        }
+    }
+
+    /// Polls the gateway until a running software update has finished and returns the new
+    /// configuration. `on_progress` is called whenever the update state changes.
+    ///
+    /// The gateway restarts while installing, so network errors and error statuses are ignored
+    /// until the timeout, also when they are returned after retries.
+    pub async fn wait_for_software_update<Progress>(
+        &self,
+        options: UpdatePollOptions,
+        mut on_progress: Progress,
+    ) -> Result<GatewayConfig, DeconzError>
+        where
+            Progress: FnMut(UpdateState),
+    {
+        let deadline = Instant::now() + options.timeout;
+        let initial = self.get_config().await?;
+        let mut last_state = update_state(&initial);
+        let mut started = last_state != UpdateState::NoUpdate;
+        on_progress(last_state);
+
+        loop {
+            if Instant::now() + options.interval > deadline {
+                return Err(DeconzError::UpdateTimeout { last_state });
+            }
+            tokio::time::sleep(options.interval).await;
+
+            let config = match self.get_config().await {
+                Ok(config) => config,
+                Err(err) if matches!(err.last_error(), DeconzError::Network(_) | DeconzError::Status { .. }) => continue,
+                Err(err) => return Err(err),
+            };
+            let state = update_state(&config);
+            if state != last_state {
+                on_progress(state);
+                last_state = state;
+            }
+            started |= state != UpdateState::NoUpdate;
+
+            if config.sw_version != initial.sw_version || (started && state == UpdateState::NoUpdate) {
+                return Ok(config);
+            }
+        }
+    }
+
+    /// Triggers a software update and waits until it has been installed.
+    pub async fn update_software<Progress>(
+        &self,
+        options: UpdatePollOptions,
+        on_progress: Progress,
+    ) -> Result<GatewayConfig, DeconzError>
+        where
+            Progress: FnMut(UpdateState),
+    {
+        self.trigger_software_update().await?;
+        self.wait_for_software_update(options, on_progress).await
+    }
+}
+
+fn update_state(config: &GatewayConfig) -> UpdateState {
+    config
+        .sw_update
+        .as_ref()
+        .map(|update| update.update_state)
+        .unwrap_or(UpdateState::NoUpdate)
+}
+
+
+#[cfg(test)]
+mod software_update_tests {
+    use std::time::Duration;
+
+    use httpmock::prelude::*;
+    use url::Url;
+
+    use crate::connection::DeconzConnection;
+    use crate::endpoints::configuration::UpdateState;
+    use crate::retry::RetryPolicy;
+    use crate::software_update::UpdatePollOptions;
+    use crate::test_server::{sequence_server, Reply};
+    use crate::version::ApiVersion;
+
+    fn config(update_state: u8, sw_version: &str) -> Reply {
+        let mut config: serde_json::Value =
+            serde_json::from_str(include_str!("test-api-responses/get-configuration.json")).unwrap();
+        config["swupdate"]["updatestate"] = update_state.into();
+        config["swversion"] = sw_version.into();
+        Reply::Json(200, config.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_software_update() {
+        let (url, server) = sequence_server(vec![
+            config(0, "2.6.0"),
+            config(0, "2.6.0"),
+            config(1, "2.6.0"),
+            config(3, "2.6.0"),
+            Reply::Close,
+            Reply::Json(503, "Service Unavailable".to_string()),
+            config(0, "2.7.0"),
+        ])
+        .await;
+        let connection = DeconzConnection::builder(url)
+            .api_key("KEY")
+            .retry_policy(RetryPolicy::new(1))
+            .build()
+            .unwrap();
+        let options = UpdatePollOptions {
+            interval: Duration::from_millis(1),
+            timeout: Duration::from_secs(5),
+        };
+        let mut progress = vec![];
+        assert_eq!(connection.gateway_version().await.unwrap().sw_version, ApiVersion::new(2, 6, 0));
+
+        let config = connection.wait_for_software_update(options, |state| progress.push(state)).await.unwrap();
+        assert_eq!(config.sw_version, "2.7.0");
+        assert_eq!(progress, vec![
+            UpdateState::NoUpdate,
+            UpdateState::Downloading,
+            UpdateState::Installing,
+            UpdateState::NoUpdate,
+        ]);
+        assert_eq!(connection.gateway_version().await.unwrap().sw_version, ApiVersion::new(2, 7, 0));
+        assert_eq!(server.await.unwrap().len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_trigger_software_update() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST).path("/api/KEY/config/update");
+            then.status(200).body(include_str!("test-api-responses/update-software.json"));
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let connection = DeconzConnection::new(url, "KEY".to_string()).unwrap();
+
+        assert_eq!(connection.trigger_software_update().await.unwrap(), "2.04.05");
+        mock.assert();
+    }
+}
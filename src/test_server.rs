@@ -0,0 +1,59 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use url::Url;
+
+/// A canned answer of [`sequence_server`].
+pub(crate) enum Reply {
+    Json(u16, String),
+    /// Closes the connection without answering, which the client sees as a network error.
+    Close,
+}
+
+/// Starts a server that answers the n-th request with the n-th reply, for tests where the same
+/// request has to be answered differently over time. The handle returns the request lines.
+pub(crate) async fn sequence_server(replies: Vec<Reply>) -> (Url, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+    let handle = tokio::spawn(async move {
+        let mut requests = Vec::new();
+        for reply in replies {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            requests.push(read_request_line(&mut stream).await);
+            let Reply::Json(status, body) = reply else {
+                continue;
+            };
+            let response = format!(
+                "HTTP/1.1 {status} Test\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+        requests
+    });
+    (url, handle)
+}
+
+/// Reads the whole request and returns its first line, e.g. `GET /api/KEY/config HTTP/1.1`.
+async fn read_request_line(stream: &mut tokio::net::TcpStream) -> String {
+    let mut request = Vec::new();
+    let mut buffer = [0; 4096];
+    loop {
+        let read = stream.read(&mut buffer).await.unwrap();
+        request.extend_from_slice(&buffer[..read]);
+        let text = String::from_utf8_lossy(&request);
+        if let Some(end) = text.find("\r\n\r\n") {
+            let content_length = text[..end]
+                .lines()
+                .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|len| len.trim().parse::<usize>().unwrap()))
+                .unwrap_or(0);
+            if read == 0 || request.len() >= end + 4 + content_length {
+                return text.lines().next().unwrap_or_default().to_string();
+            }
+        }
+        if read == 0 {
+            return String::new();
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::connection::{DeconzConnection, DeconzError};
+use crate::endpoints::configuration::GatewayConfig;
+
+/// A `major.minor.patch` version as reported in `apiversion` and `swversion`.
+///
+/// Parts are compared numerically, so `2.05.88` < `2.6.0`. Missing parts count as zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ApiVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl ApiVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> ApiVersion {
+        ApiVersion { major, minor, patch }
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = DeconzError;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let invalid = || DeconzError::InvalidParameter {
+            parameter: "version",
+            reason: format!("'{version}' is not a major.minor.patch version"),
+        };
+        let mut parts = version.trim().split('.').map(|part| part.parse::<u32>().map_err(|_| invalid()));
+        let major = parts.next().ok_or_else(invalid)??;
+        let minor = parts.next().transpose()?.unwrap_or(0);
+        let patch = parts.next().transpose()?.unwrap_or(0);
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(ApiVersion { major, minor, patch })
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The oldest gateway software that can export and import backups.
+pub const BACKUP_SW_VERSION: ApiVersion = ApiVersion::new(2, 5, 0);
+/// The oldest gateway software that knows the `websocketnotifyall` setting.
+pub const WEBSOCKET_NOTIFY_ALL_SW_VERSION: ApiVersion = ApiVersion::new(2, 5, 0);
+
+/// The REST API and software version of a gateway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GatewayVersion {
+    pub api_version: ApiVersion,
+    pub sw_version: ApiVersion,
+}
+
+impl GatewayVersion {
+    pub(crate) fn from_config(config: &GatewayConfig) -> Result<GatewayVersion, DeconzError> {
+        Ok(GatewayVersion {
+            api_version: config.api_version.parse()?,
+            sw_version: config.sw_version.parse()?,
+        })
+    }
+}
+
+impl DeconzConnection {
+    /// Returns the gateway version, fetching it if it isn't cached yet.
+    ///
+    /// The cache is refreshed by every [`Self::get_config`], e.g. while waiting for a software
+    /// update, so the version stays current after an update.
+    pub async fn gateway_version(&self) -> Result<GatewayVersion, DeconzError> {
+        if let Some(version) = *self.version_cache().lock().unwrap() {
+            return Ok(version);
+        }
+        GatewayVersion::from_config(&self.get_config().await?)
+    }
+
+    /// Fails with [`DeconzError::UnsupportedVersion`] if the gateway's API is older than `required`.
+    pub async fn require_api_version(&self, required: ApiVersion, feature: &'static str) -> Result<(), DeconzError> {
+        require(feature, required, self.gateway_version().await?.api_version)
+    }
+
+    /// Fails with [`DeconzError::UnsupportedVersion`] if the gateway software is older than
+    /// `required`. Most features depend on the software version, the API version rarely changes.
+    pub async fn require_sw_version(&self, required: ApiVersion, feature: &'static str) -> Result<(), DeconzError> {
+        require(feature, required, self.gateway_version().await?.sw_version)
+    }
+}
+
+fn require(feature: &'static str, required: ApiVersion, actual: ApiVersion) -> Result<(), DeconzError> {
+    if actual < required {
+        return Err(DeconzError::UnsupportedVersion {
+            feature,
+            required,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod version_tests {
+    use httpmock::prelude::*;
+    use url::Url;
+
+    use crate::connection::{DeconzConnection, DeconzError};
+    use crate::endpoints::configuration::ConfigUpdate;
+    use crate::version::ApiVersion;
+
+    #[test]
+    fn test_parse_and_compare() {
+        assert_eq!("1.16.0".parse::<ApiVersion>().unwrap(), ApiVersion::new(1, 16, 0));
+        assert_eq!("2.05".parse::<ApiVersion>().unwrap(), ApiVersion::new(2, 5, 0));
+        assert!("2.05.88".parse::<ApiVersion>().unwrap() < "2.6.0".parse().unwrap());
+        assert!("0x26660700".parse::<ApiVersion>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_version_is_cached() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/config");
+            then.status(200).body(include_str!("test-api-responses/get-configuration.json"));
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let connection = DeconzConnection::new(url, "KEY".to_string()).unwrap();
+
+        let version = connection.gateway_version().await.unwrap();
+        assert_eq!(version.api_version, ApiVersion::new(1, 16, 0));
+        assert_eq!(version.sw_version, ApiVersion::new(2, 6, 0));
+        connection.require_api_version(ApiVersion::new(1, 10, 0), "test").await.unwrap();
+        let result = connection.require_api_version(ApiVersion::new(1, 17, 0), "test").await;
+        assert!(matches!(result, Err(DeconzError::UnsupportedVersion { feature: "test", .. })));
+        mock.assert_hits(1);
+    }
+
+    #[tokio::test]
+    async fn test_gated_methods_on_old_gateway() {
+        let server = MockServer::start();
+        let mut config: serde_json::Value =
+            serde_json::from_str(include_str!("test-api-responses/get-configuration.json")).unwrap();
+        config["swversion"] = "2.04.40".into();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/KEY/config");
+            then.status(200).json_body(config);
+        });
+        let export = server.mock(|when, then| {
+            when.method(POST).path("/api/KEY/config/export");
+            then.status(200).body(r#"[{"success": {"/config/export": "success"}}]"#);
+        });
+        let modify = server.mock(|when, then| {
+            when.method(PUT).path("/api/KEY/config");
+            then.status(200).body(r#"[{"success": {"/config/websocketnotifyall": true}}]"#);
+        });
+        let url = Url::parse(&server.base_url()).unwrap();
+        let connection = DeconzConnection::new(url, "KEY".to_string()).unwrap();
+
+        match connection.create_backup().await {
+            Err(DeconzError::UnsupportedVersion { feature, required, actual }) => {
+                assert_eq!(feature, "config/export");
+                assert_eq!(required, ApiVersion::new(2, 5, 0));
+                assert_eq!(actual, ApiVersion::new(2, 4, 40));
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+        let update = ConfigUpdate { websocket_notify_all: Some(true), ..Default::default() };
+        let result = connection.modify_config(&update).await;
+        assert!(matches!(result, Err(DeconzError::UnsupportedVersion { feature: "websocketnotifyall", .. })));
+        export.assert_hits(0);
+        modify.assert_hits(0);
+    }
+}